@@ -1,6 +1,5 @@
 fn main() {
-    let mut service = service::Service::new("example-service".into());
-    if service.exists() {
+    if service::Service::new("example-service".into()).exists() {
         panic!("Service already exists");
     }
 
@@ -8,21 +7,12 @@ fn main() {
     exe.pop();
     let exe = exe.join("example-service-async");
 
-    let mut service_config = service::ServiceConfig::new(
+    let mut service = service::Service::install(
+        "example-service",
+        exe,
         vec!["example-arg1".to_string(), "arg2".to_string()],
         "The Example service".into(),
-        exe,
-        None,
-    );
-    #[cfg(target_os = "linux")]
-    {
-        service_config.config_path = std::path::PathBuf::from("./");
-    }
-    #[cfg(target_family = "windows")]
-    {
-        service_config.display = "Example service".into();
-        service_config.user_password = None;
-    }
-    service.create(service_config).unwrap();
+    )
+    .unwrap();
     service.start().unwrap();
 }
@@ -3,7 +3,5 @@ fn main() {
     if !service.exists() {
         panic!("Service does not exist");
     }
-    let e = service.stop();
-    println!("Stop service result is {:?}", e);
-    service.delete().unwrap();
+    service.teardown().unwrap();
 }
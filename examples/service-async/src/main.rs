@@ -1,10 +1,26 @@
-async fn smain() {
+async fn smain(
+    mut rx: tokio::sync::mpsc::Receiver<service::ServiceEvent<u64>>,
+    _tx: tokio::sync::mpsc::Sender<service::ServiceEvent<u64>>,
+    args: Vec<String>,
+    _standalone_mode: bool,
+) -> service::ServiceExit {
+    service::log::debug!("Service args are now {:?}", args);
     let mut i = 0;
     loop {
-        std::thread::sleep(std::time::Duration::from_millis(1000));
-        i += 1;
-        service::log::debug!("I am groot async {}", i);
+        tokio::select! {
+            Some(event) = rx.recv() => {
+                if let service::ServiceEvent::Stop = event {
+                    service::log::debug!("Stop requested");
+                    break;
+                }
+            }
+            _ = tokio::time::sleep(std::time::Duration::from_millis(1000)) => {
+                i += 1;
+                service::log::debug!("I am groot async {}", i);
+            }
+        }
     }
+    service::ServiceExit::success()
 }
 
 service::ServiceAsyncMacro!(service_starter, smain, u64);
@@ -20,7 +36,9 @@ async fn main() {
     }
 
     let service = service::Service::new("example-service".into());
-    service.new_log(service::LogLevel::Debug);
+    if let Err(e) = service.new_log(service::LogLevel::Debug) {
+        eprintln!("Failed to initialize logging: {:?}", e);
+    }
     service::log::debug!("Service dispatching now {:?}", std::env::args());
     if let Err(e) = service::DispatchAsync!(service, service_starter) {
         service::log::error!("Failed to dispatch service: {:?}", e);
@@ -1,8 +1,14 @@
 fn smain(
     rx: Option<std::sync::mpsc::Receiver<service::ServiceEvent<u64>>>,
     _tx: Option<std::sync::mpsc::Sender<service::ServiceEvent<u64>>>,
-) {
-    service::log::debug!("Service args are now {:?}", std::env::args());
+    args: Vec<String>,
+    standalone_mode: bool,
+) -> service::ServiceExit {
+    service::log::debug!(
+        "Service args are now {:?}, standalone {}",
+        args,
+        standalone_mode
+    );
     let mut i = 0;
     loop {
         std::thread::sleep(std::time::Duration::from_millis(1000));
@@ -14,14 +20,21 @@ fn smain(
             }
         }
     }
+    service::ServiceExit::success()
 }
 
 service::ServiceMacro!(service_starter, smain, u64);
 
 fn main() {
     let service = service::Service::new("example-service".into());
-    service.new_log(service::LogLevel::Debug);
-    if let Err(e) = service.dispatch(service_starter) {
-        service::log::error!("Failed to dispatch service: {:?}", e);
+    if let Err(e) = service.new_log(service::LogLevel::Debug) {
+        eprintln!("Failed to initialize logging: {:?}", e);
+    }
+    if service::Service::is_launched_as_service() {
+        if let Err(e) = service.dispatch(service_starter) {
+            service::log::error!("Failed to dispatch service: {:?}", e);
+        }
+    } else {
+        std::process::exit(service.run_standalone(smain) as i32);
     }
 }
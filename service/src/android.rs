@@ -0,0 +1,344 @@
+//! Android specific code for running a service
+//!
+//! Android has no service manager that services can be installed into, they are declared in the manifest of the app.
+//! The foreground service of the app runs the service function, its `onStartCommand` calls [Service::dispatch] and its `onDestroy` calls [Service::stop], through the JNI entry points of the app.
+//! One service is run per process. The async feature is not supported on android.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::ServiceFn;
+
+/// Dummy function for uniformity to windows
+pub type DispatchFn = fn();
+
+#[derive(Debug)]
+/// Errors that can occur when controlling a service
+pub enum StartStopError {
+    /// The operation is done by android for the services declared in the manifest of the app
+    Unsupported,
+}
+
+/// The macro generates the service function required
+#[macro_export]
+macro_rules! ServiceMacro {
+    ($entry:ident, $function:ident, $t:ident) => {
+        fn $entry() {
+            service::run_service::<$t>($function);
+        }
+    };
+    ($entry:ident, $function:ident, $t:ident, $config:expr) => {
+        fn $entry() {
+            let _config: service::RunConfig<$t> = $config;
+            service::run_service::<$t>($function);
+        }
+    };
+}
+
+#[derive(Debug)]
+/// Errors that can occur when creating a service
+pub enum CreateError {
+    /// Services are declared in the manifest of the app, they cannot be created at run time
+    Unsupported,
+}
+
+#[derive(Clone, Debug)]
+/// A placeholder, not currently used
+pub struct Session(String);
+
+/// The configuration for constructing a Service, kept for uniformity with the other platforms since android services cannot be created
+pub struct ServiceConfig {
+    /// The arguments for the service
+    arguments: Vec<String>,
+    /// The description of the service as presented to the user
+    description: String,
+    /// The path to the service binary
+    binary: PathBuf,
+    /// The username that the service should run as
+    username: Option<String>,
+    /// The environment variables provided to the service
+    pub environment: Vec<(String, String)>,
+    /// The path to the configuration data for the service
+    pub config_path: PathBuf,
+    /// Allow creating the service before its binary exists
+    pub allow_missing_binary: bool,
+    /// Other services that must be started before this one
+    pub dependencies: Vec<String>,
+}
+
+impl ServiceConfig {
+    /// Build a new service config with reasonable defaults.
+    /// # Arguments
+    /// * arguments - The list of arguments to provide to the service
+    /// * description - The description of the service
+    /// * binary - The path to the binary that runs the service
+    /// * username - The username the service runs as
+    pub fn new(
+        arguments: Vec<String>,
+        description: String,
+        binary: PathBuf,
+        username: Option<String>,
+    ) -> Self {
+        Self {
+            arguments,
+            description,
+            binary,
+            username,
+            environment: Vec::new(),
+            config_path: PathBuf::new(),
+            allow_missing_binary: false,
+            dependencies: Vec::new(),
+        }
+    }
+
+    /// The arguments for the service
+    pub fn arguments(&self) -> &[String] {
+        &self.arguments
+    }
+
+    /// The description of the service
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// The path to the binary that runs the service
+    pub fn binary(&self) -> &std::path::Path {
+        &self.binary
+    }
+
+    /// The username the service runs as
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+}
+
+/// The state of the service function run by dispatch
+enum State {
+    /// No service function is running
+    Stopped,
+    /// dispatch started the thread of the service, which has not set up its channel yet
+    Starting,
+    /// A stop was requested before the service set up its channel, it is delivered once it has
+    StopRequested,
+    /// The service function is running, the function sends it [crate::ServiceEvent::Stop]
+    Running(Box<dyn Fn() + Send>),
+}
+
+/// The state of the service of this process
+static STATE: Mutex<State> = Mutex::new(State::Stopped);
+
+/// The priorities of the android log for the levels of the log crate
+fn log_priority(level: log::Level) -> std::os::raw::c_int {
+    match level {
+        log::Level::Trace => 2,
+        log::Level::Debug => 3,
+        log::Level::Info => 4,
+        log::Level::Warn => 5,
+        log::Level::Error => 6,
+    }
+}
+
+#[link(name = "log")]
+extern "C" {
+    /// Write a message to the android log
+    fn __android_log_write(
+        prio: std::os::raw::c_int,
+        tag: *const std::os::raw::c_char,
+        text: *const std::os::raw::c_char,
+    ) -> std::os::raw::c_int;
+}
+
+/// A logger that writes to the android log, shown by logcat
+struct Logcat {
+    /// The tag of the messages, the name of the service
+    tag: std::ffi::CString,
+    /// The most verbose level that is logged
+    level: log::Level,
+}
+
+impl log::Log for Logcat {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let text = record.args().to_string().replace('\0', "");
+        let text = std::ffi::CString::new(text).unwrap_or_default();
+        unsafe {
+            __android_log_write(
+                log_priority(record.level()),
+                self.tag.as_ptr(),
+                text.as_ptr(),
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Represents a service on the system
+pub struct Service {
+    /// The name of the service, used as the tag of its log messages
+    name: String,
+}
+
+impl Service {
+    /// Construct a new self, this panics when the name is not a valid service name, see [Service::try_new]
+    pub fn new(name: String) -> Self {
+        Self::try_new(&name).unwrap()
+    }
+
+    /// Construct a new self, checking that the name is not empty and has no nul character
+    pub fn try_new(name: &str) -> Result<Self, crate::InvalidServiceName> {
+        if name.is_empty() {
+            return Err(crate::InvalidServiceName::Empty);
+        }
+        if name.contains('\0') {
+            return Err(crate::InvalidServiceName::InvalidCharacter('\0'));
+        }
+        Ok(Self {
+            name: name.to_string(),
+        })
+    }
+
+    /// Returns true once dispatch has started the service in the current process
+    pub fn is_launched_as_service() -> bool {
+        !matches!(*STATE.lock().unwrap(), State::Stopped)
+    }
+
+    /// The name of the service
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Initialize a new log instance that writes to the android log, with the name of the service as the tag
+    pub fn new_log(&self, level: super::LogLevel) -> Result<(), super::LogInitError> {
+        let Some(level) = level.level() else {
+            log::set_max_level(log::LevelFilter::Off);
+            return Ok(());
+        };
+        let tag = std::ffi::CString::new(self.name.as_str())
+            .map_err(|e| super::LogInitError::BackendFailed(e.to_string()))?;
+        let logger = Box::leak(Box::new(Logcat { tag, level }));
+        log::set_logger(logger)?;
+        log::set_max_level(level.to_level_filter());
+        Ok(())
+    }
+
+    /// Services are declared in the manifest of the app, so this always fails
+    pub fn create(&mut self, _config: ServiceConfig) -> Result<(), CreateError> {
+        Err(CreateError::Unsupported)
+    }
+
+    /// Services are declared in the manifest of the app, so this always fails
+    pub fn delete(&mut self) -> Result<(), std::io::Error> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "android services are declared in the manifest of the app",
+        ))
+    }
+
+    /// The app starts its services, so this always fails
+    pub fn start(&mut self) -> Result<(), StartStopError> {
+        Err(StartStopError::Unsupported)
+    }
+
+    /// Send [crate::ServiceEvent::Stop] to the service started with dispatch, this returns without waiting for the service function to return.
+    /// Stopping a service that is not running succeeds
+    pub fn stop(&mut self) -> Result<(), StartStopError> {
+        let mut state = STATE.lock().unwrap();
+        match &*state {
+            State::Running(stop) => stop(),
+            State::Starting => *state = State::StopRequested,
+            State::Stopped | State::StopRequested => {}
+        }
+        Ok(())
+    }
+
+    /// Services declared in the manifest of the app are always enabled, so this does nothing
+    pub fn enable(&mut self) -> Result<(), StartStopError> {
+        Ok(())
+    }
+
+    /// Services declared in the manifest of the app cannot be disabled, so this always fails
+    pub fn disable(&mut self) -> Result<(), StartStopError> {
+        Err(StartStopError::Unsupported)
+    }
+
+    /// The status of the service started with dispatch in the current process
+    pub fn status(&self) -> Result<crate::ServiceStatus, StartStopError> {
+        Ok(match *STATE.lock().unwrap() {
+            State::Stopped => crate::ServiceStatus::Stopped,
+            State::Starting => crate::ServiceStatus::StartPending,
+            State::StopRequested => crate::ServiceStatus::StopPending,
+            State::Running(_) => crate::ServiceStatus::Running,
+        })
+    }
+
+    /// Returns true when the service started with dispatch is running in the current process
+    pub fn is_running(&self) -> bool {
+        matches!(self.status(), Ok(crate::ServiceStatus::Running))
+    }
+
+    /// Returns true when the service started with dispatch is running in the current process, services cannot be installed otherwise
+    pub fn exists(&self) -> bool {
+        self.is_running()
+    }
+
+    /// Run the service function in the foreground, [Service::stop] stops the service
+    pub fn run_standalone<T: Send + 'static>(&self, service_main: ServiceFn<T>) -> u32 {
+        run_with_stop(service_main, true)
+    }
+
+    /// Run the service on a dedicated thread and return, so that it can be called from `onStartCommand`.
+    /// Nothing is done while the service is already running
+    pub fn dispatch(&self, service_main: DispatchFn) -> Result<(), u32> {
+        let mut state = STATE.lock().unwrap();
+        if !matches!(*state, State::Stopped) {
+            return Ok(());
+        }
+        *state = State::Starting;
+        drop(state);
+        let thread = std::thread::Builder::new()
+            .name(self.name.clone())
+            .spawn(move || {
+                service_main();
+                *STATE.lock().unwrap() = State::Stopped;
+            });
+        if let Err(e) = thread {
+            log::error!("Unable to start the service thread: {:?}", e);
+            *STATE.lock().unwrap() = State::Stopped;
+            return Err(1);
+        }
+        Ok(())
+    }
+}
+
+/// Runs the main service function, delivering [Service::stop] to it as [crate::ServiceEvent::Stop].
+/// A non zero exit code is logged, the process keeps running since it belongs to the app.
+pub fn run_service<T: Send + 'static>(service_main: ServiceFn<T>) {
+    run_with_stop(service_main, false);
+}
+
+/// Run the service function with [Service::stop] delivered as a service event, returning its exit code
+fn run_with_stop<T: Send + 'static>(service_main: ServiceFn<T>, standalone_mode: bool) -> u32 {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let tx2: std::sync::mpsc::Sender<crate::ServiceEvent<T>> = tx.clone();
+    let stop = move || {
+        let _ = tx.send(crate::ServiceEvent::Stop);
+    };
+    {
+        let mut state = STATE.lock().unwrap();
+        if matches!(*state, State::StopRequested) {
+            stop();
+        }
+        *state = State::Running(Box::new(stop));
+    }
+    let args = std::env::args().collect();
+    let code = service_main(Some(rx), Some(tx2), args, standalone_mode).report();
+    *STATE.lock().unwrap() = State::Stopped;
+    code
+}
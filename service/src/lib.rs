@@ -6,11 +6,15 @@
 
 pub use log;
 
+#[cfg(feature = "tracing")]
+pub use tracing;
+
 #[cfg(feature = "egui-prompt")]
 use userprompt::egui;
 
-/// The various levels of log, increasing in severity
-#[derive(Clone, Debug)]
+/// The various levels of log, increasing in severity.
+/// Levels compare in the order they are declared, so `level >= LogLevel::Warning` holds for warnings, errors, and off
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "prompt", derive(userprompt::Prompting))]
 #[cfg_attr(feature = "egui-prompt", derive(userprompt::EguiPrompting))]
@@ -25,6 +29,8 @@ pub enum LogLevel {
     Warning,
     /// Error
     Error,
+    /// No logging at all
+    Off,
 }
 
 impl Default for LogLevel {
@@ -33,6 +39,121 @@ impl Default for LogLevel {
     }
 }
 
+/// The error returned when a string is not a known log level
+#[derive(Debug)]
+pub struct LogLevelParseError(String);
+
+impl std::fmt::Display for LogLevelParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown log level {}", self.0)
+    }
+}
+
+impl std::error::Error for LogLevelParseError {}
+
+impl std::str::FromStr for LogLevel {
+    type Err = LogLevelParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "trace" => Ok(Self::Trace),
+            "debug" => Ok(Self::Debug),
+            "info" => Ok(Self::Info),
+            "warn" | "warning" => Ok(Self::Warning),
+            "error" => Ok(Self::Error),
+            "off" => Ok(Self::Off),
+            _ => Err(LogLevelParseError(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            LogLevel::Trace => "trace",
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warning => "warning",
+            LogLevel::Error => "error",
+            LogLevel::Off => "off",
+        };
+        f.write_str(s)
+    }
+}
+
+/// The errors that can occur when setting up logging for a service
+#[derive(Debug)]
+pub enum LogInitError {
+    /// A logger has already been installed for this process
+    AlreadyInitialized,
+    /// The platform logging backend could not be set up
+    BackendFailed(String),
+}
+
+impl From<log::SetLoggerError> for LogInitError {
+    fn from(_value: log::SetLoggerError) -> Self {
+        Self::AlreadyInitialized
+    }
+}
+
+/// The reasons that a name cannot be used for a service on the current platform
+#[derive(Debug)]
+pub enum InvalidServiceName {
+    /// The name is empty
+    Empty,
+    /// The name is longer than the platform allows, with the maximum length
+    TooLong(usize),
+    /// The name contains a character that the platform does not allow
+    InvalidCharacter(char),
+    /// The name ends with a suffix that the platform reserves, such as `.target` for systemd
+    ReservedSuffix(String),
+}
+
+impl std::fmt::Display for InvalidServiceName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidServiceName::Empty => write!(f, "the service name is empty"),
+            InvalidServiceName::TooLong(max) => {
+                write!(f, "the service name is longer than {} characters", max)
+            }
+            InvalidServiceName::InvalidCharacter(c) => {
+                write!(f, "the service name contains the invalid character {:?}", c)
+            }
+            InvalidServiceName::ReservedSuffix(s) => {
+                write!(f, "the service name ends with the reserved suffix {}", s)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidServiceName {}
+
+/// The state of a service as reported by the operating system
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServiceStatus {
+    /// The service is running
+    Running,
+    /// The service is not running
+    Stopped,
+    /// The service is paused
+    Paused,
+    /// The service is in the process of starting
+    StartPending,
+    /// The service is in the process of stopping
+    StopPending,
+    /// The service is not installed on the system
+    NotInstalled,
+}
+
+/// What [Service::ensure] did to make the installed service match the config
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnsureOutcome {
+    /// The service did not exist and was created
+    Created,
+    /// The service already existed and its configuration was updated
+    Updated,
+}
+
 #[derive(Debug)]
 /// The events that can be sent to the service handler
 pub enum ServiceEvent<T> {
@@ -42,6 +163,8 @@ pub enum ServiceEvent<T> {
     Pause,
     /// Stop the service
     Stop,
+    /// Reload the configuration of the service
+    Reload,
     /// Windows specific session message
     SessionConnect(Session),
     /// Windows specific session message
@@ -58,6 +181,10 @@ pub enum ServiceEvent<T> {
     SessionLock(Session),
     /// Windows specific session message
     SessionUnlock(Session),
+    /// The computer is about to suspend, windows only for now
+    PowerSuspend,
+    /// The computer resumed from suspend, windows only for now
+    PowerResume,
     /// A custom message for the service
     Custom(T),
 }
@@ -71,31 +198,411 @@ impl LogLevel {
             crate::LogLevel::Warning => log::LevelFilter::Warn,
             crate::LogLevel::Error => log::LevelFilter::Error,
             crate::LogLevel::Trace => log::LevelFilter::Trace,
+            crate::LogLevel::Off => log::LevelFilter::Off,
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    /// Convert log level to a tracing level filter
+    pub fn tracing_level_filter(&self) -> tracing::level_filters::LevelFilter {
+        match self {
+            crate::LogLevel::Debug => tracing::level_filters::LevelFilter::DEBUG,
+            crate::LogLevel::Info => tracing::level_filters::LevelFilter::INFO,
+            crate::LogLevel::Warning => tracing::level_filters::LevelFilter::WARN,
+            crate::LogLevel::Error => tracing::level_filters::LevelFilter::ERROR,
+            crate::LogLevel::Trace => tracing::level_filters::LevelFilter::TRACE,
+            crate::LogLevel::Off => tracing::level_filters::LevelFilter::OFF,
         }
     }
 
-    /// Convert self to a log::Level
-    pub fn level(&self) -> log::Level {
+    /// Convert self to a log::Level, none when logging is off
+    pub fn level(&self) -> Option<log::Level> {
         match self {
-            crate::LogLevel::Debug => log::Level::Debug,
-            crate::LogLevel::Info => log::Level::Info,
-            crate::LogLevel::Warning => log::Level::Warn,
-            crate::LogLevel::Error => log::Level::Error,
-            crate::LogLevel::Trace => log::Level::Trace,
+            crate::LogLevel::Debug => Some(log::Level::Debug),
+            crate::LogLevel::Info => Some(log::Level::Info),
+            crate::LogLevel::Warning => Some(log::Level::Warn),
+            crate::LogLevel::Error => Some(log::Level::Error),
+            crate::LogLevel::Trace => Some(log::Level::Trace),
+            crate::LogLevel::Off => None,
+        }
+    }
+}
+
+/// How the service function finished, returned by the service function on every platform
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ServiceExit {
+    /// The exit code of the service, zero when the service stopped without an error
+    pub code: u32,
+    /// Why the service stopped, logged when the service function returns
+    pub message: Option<String>,
+}
+
+impl ServiceExit {
+    /// A successful exit without a message
+    pub fn success() -> Self {
+        Self::default()
+    }
+
+    /// An exit with the code and the reason the service stopped
+    pub fn with_message(code: u32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: Some(message.into()),
+        }
+    }
+
+    /// Log the message, as an error when the code is not zero, and return the code
+    pub(crate) fn report(self) -> u32 {
+        if let Some(message) = &self.message {
+            if self.code == 0 {
+                log::info!("The service stopped: {}", message);
+            } else {
+                let code = self.code;
+                log::error!("The service stopped with exit code {}: {}", code, message);
+            }
+        }
+        self.code
+    }
+}
+
+impl From<u32> for ServiceExit {
+    fn from(code: u32) -> Self {
+        Self {
+            code,
+            message: None,
+        }
+    }
+}
+
+/// The type for the service function, the same on every platform.
+/// It receives the service events, a sender for the same channel, the arguments of the service, and whether it was started with run_standalone instead of by the operating system.
+/// It returns how the service stopped, a code of zero when it stopped without an error.
+pub type ServiceFn<T> = fn(
+    rx: Option<std::sync::mpsc::Receiver<ServiceEvent<T>>>,
+    tx: Option<std::sync::mpsc::Sender<ServiceEvent<T>>>,
+    args: Vec<String>,
+    standalone_mode: bool,
+) -> ServiceExit;
+
+/// Options for how a running service interacts with the operating system, passed as the optional last argument of the service macros.
+/// Options that do not apply to the current platform are ignored. T is the type of the custom messages of the service
+pub struct RunConfig<T = ()> {
+    /// The service accepts pause and continue requests, windows only
+    pub accept_pause_continue: bool,
+    /// The service receives the session events, windows only
+    pub accept_session_changes: bool,
+    /// The service receives [ServiceEvent::PowerSuspend] and [ServiceEvent::PowerResume], windows only
+    pub accept_power_events: bool,
+    /// The service is told about a system shutdown early, with the time set by the preshutdown timeout of its config, windows only
+    pub accept_preshutdown: bool,
+    /// The service stays in the start pending state until it calls `Service::report_running`, windows only
+    pub wait_for_ready: bool,
+    /// How long the service function has to return once it is told to stop, none waits forever.
+    /// When it elapses the service is reported as stopped with an error and the service function is left behind, windows only
+    pub stop_drain_timeout: Option<std::time::Duration>,
+    /// The name of the service, used when windows starts the service without passing its name as the first argument, windows only
+    pub service_name: Option<String>,
+    /// Maps custom control codes into [ServiceEvent::Custom]
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub(crate) custom_control: Option<fn(u32) -> Option<T>>,
+}
+
+impl<T> Default for RunConfig<T> {
+    fn default() -> Self {
+        Self {
+            accept_pause_continue: false,
+            accept_session_changes: false,
+            accept_power_events: false,
+            accept_preshutdown: false,
+            wait_for_ready: false,
+            stop_drain_timeout: None,
+            service_name: None,
+            custom_control: None,
+        }
+    }
+}
+
+impl<T> Clone for RunConfig<T> {
+    fn clone(&self) -> Self {
+        Self {
+            service_name: self.service_name.clone(),
+            ..*self
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for RunConfig<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RunConfig")
+            .field("accept_pause_continue", &self.accept_pause_continue)
+            .field("accept_session_changes", &self.accept_session_changes)
+            .field("accept_power_events", &self.accept_power_events)
+            .field("accept_preshutdown", &self.accept_preshutdown)
+            .field("wait_for_ready", &self.wait_for_ready)
+            .field("stop_drain_timeout", &self.stop_drain_timeout)
+            .field("service_name", &self.service_name)
+            .field("custom_control", &self.custom_control.is_some())
+            .finish()
+    }
+}
+
+impl<T> RunConfig<T> {
+    /// Deliver the custom control codes 129 to 255 to the service as [ServiceEvent::Custom], using map to turn the code into the custom message.
+    /// Codes that map to none are ignored. Code 128 is always delivered as [ServiceEvent::Reload]. Windows only
+    pub fn custom_control(mut self, map: fn(u32) -> Option<T>) -> Self {
+        self.custom_control = Some(map);
+        self
+    }
+}
+
+/// Builds a [ServiceConfig] the same way on every platform. Settings that do not apply to the current platform are ignored.
+#[derive(Default)]
+pub struct ServiceConfigBuilder {
+    /// The arguments for the service
+    arguments: Vec<String>,
+    /// The description of the service as presented to the user
+    description: String,
+    /// The path to the service binary
+    binary: std::path::PathBuf,
+    /// The username that the service should run as
+    username: Option<String>,
+    /// The environment variables provided to the service
+    environment: Vec<(String, String)>,
+    /// The display name of the service, windows only
+    #[cfg_attr(not(windows), allow(dead_code))]
+    display: String,
+    /// The password for the user that the service should run as, windows only
+    #[cfg_attr(not(windows), allow(dead_code))]
+    user_password: Option<String>,
+    /// Other services that must be started before this one, not used on macos
+    #[cfg_attr(target_os = "macos", allow(dead_code))]
+    dependencies: Vec<String>,
+    /// The path to the configuration data for the service
+    config_path: std::path::PathBuf,
+    /// Allow creating the service before its binary exists
+    allow_missing_binary: bool,
+}
+
+impl ServiceConfigBuilder {
+    /// Construct a new builder with everything empty
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the list of arguments to provide to the service
+    pub fn arguments(mut self, arguments: Vec<String>) -> Self {
+        self.arguments = arguments;
+        self
+    }
+
+    /// Set the description of the service
+    pub fn description(mut self, description: String) -> Self {
+        self.description = description;
+        self
+    }
+
+    /// Set the path to the binary that runs the service
+    pub fn binary(mut self, binary: std::path::PathBuf) -> Self {
+        self.binary = binary;
+        self
+    }
+
+    /// Set the username the service runs as
+    pub fn username(mut self, username: Option<String>) -> Self {
+        self.username = username;
+        self
+    }
+
+    /// Add an environment variable for the service
+    pub fn environment(mut self, key: String, value: String) -> Self {
+        self.environment.push((key, value));
+        self
+    }
+
+    /// Add a service that must be started before this one, ignored on macos
+    pub fn dependency(mut self, name: String) -> Self {
+        self.dependencies.push(name);
+        self
+    }
+
+    /// Set the display name of the service, ignored except on windows
+    pub fn display(mut self, display: String) -> Self {
+        self.display = display;
+        self
+    }
+
+    /// Set the password for the user the service runs as, ignored except on windows
+    pub fn user_password(mut self, user_password: Option<String>) -> Self {
+        self.user_password = user_password;
+        self
+    }
+
+    /// Set the configuration path for the service, the platform default from `Service::default_config_path` is used when this is not set
+    pub fn config_path(mut self, config_path: std::path::PathBuf) -> Self {
+        self.config_path = config_path;
+        self
+    }
+
+    /// Allow creating the service before its binary exists, by default create fails when the binary is missing
+    pub fn allow_missing_binary(mut self, allow_missing_binary: bool) -> Self {
+        self.allow_missing_binary = allow_missing_binary;
+        self
+    }
+
+    /// Build the config for the current platform
+    pub fn build(self) -> ServiceConfig {
+        let mut config =
+            ServiceConfig::new(self.arguments, self.description, self.binary, self.username);
+        config.environment = self.environment;
+        config.config_path = self.config_path;
+        config.allow_missing_binary = self.allow_missing_binary;
+        #[cfg(not(target_os = "macos"))]
+        {
+            config.dependencies = self.dependencies;
         }
+        #[cfg(windows)]
+        {
+            config.display = self.display;
+            config.user_password = self.user_password;
+        }
+        config
+    }
+}
+
+/// The errors that can occur in [Service::install]
+#[derive(Debug)]
+pub enum InstallError {
+    /// The name cannot be used for a service on the current platform
+    InvalidName(InvalidServiceName),
+    /// The service could not be created
+    Create(CreateServiceFailure),
+    /// The service was created but could not be enabled
+    Enable(ControlError),
+}
+
+impl From<InvalidServiceName> for InstallError {
+    fn from(value: InvalidServiceName) -> Self {
+        Self::InvalidName(value)
     }
 }
 
+/// The error of stopping, enabling, or disabling a service on the current platform
+#[cfg(windows)]
+type ControlError = ServiceError;
+/// The error of stopping, enabling, or disabling a service on the current platform
+#[cfg(not(windows))]
+type ControlError = StartStopError;
+/// The error of starting a service on the current platform
+#[cfg(windows)]
+type StartError = StartServiceError;
+/// The error of starting a service on the current platform
+#[cfg(not(windows))]
+type StartError = StartStopError;
+/// The error of deleting a service on the current platform
+#[cfg(windows)]
+type DeleteError = ServiceError;
+/// The error of deleting a service on the current platform
+#[cfg(target_os = "macos")]
+type DeleteError = CreateError;
+/// The error of deleting a service on the current platform
+#[cfg(not(any(windows, target_os = "macos")))]
+type DeleteError = std::io::Error;
+/// The error of creating a service on the current platform
+#[cfg(windows)]
+type CreateServiceFailure = CreateServiceError;
+/// The error of creating a service on the current platform
+#[cfg(not(windows))]
+type CreateServiceFailure = CreateError;
+
+/// The step of [Service::teardown] that failed
+#[derive(Debug)]
+pub enum TeardownError {
+    /// The service could not be stopped
+    Stop(ControlError),
+    /// The service could not be disabled
+    Disable(ControlError),
+    /// The service could not be deleted
+    Delete(DeleteError),
+}
+
+/// The step of [Service::deploy] that failed, the steps before it are undone
+#[derive(Debug)]
+pub enum DeployError {
+    /// The service could not be created
+    Create(CreateServiceFailure),
+    /// The service could not be enabled, so it was deleted again
+    Enable(ControlError),
+    /// The service could not be started, so it was disabled and deleted again
+    Start(StartError),
+}
+
+impl Service {
+    /// Stop, disable, and delete the service, in that order. Stopping a service that is not running succeeds
+    pub fn teardown(&mut self) -> Result<(), TeardownError> {
+        self.stop().map_err(TeardownError::Stop)?;
+        self.disable().map_err(TeardownError::Disable)?;
+        self.delete().map_err(TeardownError::Delete)
+    }
+
+    /// Create, enable, and start the service, in that order.
+    /// When a step fails the earlier steps are undone as far as possible, so that the service is not left half installed
+    pub fn deploy(&mut self, config: ServiceConfig) -> Result<(), DeployError> {
+        self.create(config).map_err(DeployError::Create)?;
+        if let Err(e) = self.enable() {
+            let _ = self.delete();
+            return Err(DeployError::Enable(e));
+        }
+        if let Err(e) = self.start() {
+            let _ = self.disable();
+            let _ = self.delete();
+            return Err(DeployError::Start(e));
+        }
+        Ok(())
+    }
+
+    /// Create and enable a service with the defaults for the current platform, so that it starts at boot.
+    /// The display name on windows is the name of the service. On windows this also registers the event log source from `Service::log_source`, so that the logging of `Service::new_log` ends up in the event log.
+    /// The journal and the system log need no registration. Use [ServiceConfigBuilder] and [Service::create] for anything else
+    pub fn install(
+        name: &str,
+        binary: std::path::PathBuf,
+        arguments: Vec<String>,
+        description: String,
+    ) -> Result<Self, InstallError> {
+        let mut service = Self::try_new(name)?;
+        let config = ServiceConfigBuilder::new()
+            .arguments(arguments)
+            .description(description)
+            .binary(binary)
+            .display(name.to_string())
+            .build();
+        // create registers the event log source on windows
+        service.create(config).map_err(InstallError::Create)?;
+        service.enable().map_err(InstallError::Enable)?;
+        Ok(service)
+    }
+}
+
+#[cfg(feature = "mock")]
+pub mod mock;
+/// The mock service replaces the service of the platform, see [mock]
+#[cfg(feature = "mock")]
+pub use mock::Service;
+
 cfg_if::cfg_if! {
     if #[cfg(windows)] {
         mod windows;
         pub use winapi;
         pub use self::windows::*;
     } else if #[cfg(target_os = "macos")] {
-        todo!();
+        mod macos;
+        pub use self::macos::*;
     } else if #[cfg(target_os = "linux")] {
         mod linux;
         pub use self::linux::*;
+    } else if #[cfg(target_os = "android")] {
+        mod android;
+        pub use self::android::*;
     } else {
         todo!();
     }
@@ -2,6 +2,8 @@
 
 use std::path::PathBuf;
 
+use crate::ServiceFn;
+
 /// Dummy function for uniformity to windows
 pub type DispatchFn = fn();
 
@@ -10,8 +12,19 @@ pub type DispatchFn = fn();
 pub enum StartStopError {
     /// Systemctl does not exist or is not callable for some reason
     NoSystemCtl,
-    /// The systemctl command returned an error
-    SystemCtlFailed,
+    /// The systemctl command returned an error, with its exit code and what it printed to standard error
+    SystemCtlFailed {
+        /// The exit code of systemctl, none when it was killed by a signal
+        code: Option<i32>,
+        /// The standard error output of systemctl
+        stderr: String,
+    },
+    /// The service did not stop within the stop timeout
+    StopTimeout,
+    /// The system was not booted with systemd, so there is no service manager for systemctl to talk to
+    NotSystemd,
+    /// Systemctl was denied permission, the operation must be run as root
+    NeedsElevation,
 }
 
 /// The macro generates the service function required
@@ -19,7 +32,13 @@ pub enum StartStopError {
 macro_rules! ServiceMacro {
     ($entry:ident, $function:ident, $t:ident) => {
         fn $entry() {
-            $function(None, None);
+            service::run_service::<$t>($function);
+        }
+    };
+    ($entry:ident, $function:ident, $t:ident, $config:expr) => {
+        fn $entry() {
+            let _config: service::RunConfig<$t> = $config;
+            service::run_service::<$t>($function);
         }
     };
 }
@@ -35,12 +54,19 @@ macro_rules! DispatchAsync {
 }
 
 #[cfg(feature = "async")]
-/// The macro generates the service function required
+/// The macro generates the service function required.
+/// The service runs on the runtime of the caller, so the runtime argument of the windows macro is not accepted here
 #[macro_export]
 macro_rules! ServiceAsyncMacro {
     ($entry:ident, $function:ident, $t:ident) => {
         async fn $entry() {
-            $function().await;
+            service::run_service_async::<$t, _, _>($function).await;
+        }
+    };
+    ($entry:ident, $function:ident, $t:ident, $config:expr) => {
+        async fn $entry() {
+            let _config: service::RunConfig<$t> = $config;
+            service::run_service_async::<$t, _, _>($function).await;
         }
     };
 }
@@ -50,28 +76,467 @@ macro_rules! ServiceAsyncMacro {
 pub enum CreateError {
     /// Systemctl does not exist or is not callable for some reason
     NoSystemCtl,
-    /// The systemctl command returned an error
-    SystemCtlFailed,
+    /// The systemctl command returned an error, with its exit code and what it printed to standard error
+    SystemCtlFailed {
+        /// The exit code of systemctl, none when it was killed by a signal
+        code: Option<i32>,
+        /// The standard error output of systemctl
+        stderr: String,
+    },
     /// Systemctl reload command failed for some reason
     SystemCtlReloadFailed,
     /// Unable to create or write to the systemctl service file
     FileIoError(std::io::Error),
+    /// The service config cannot be turned into a valid unit file
+    InvalidConfig(String),
+    /// The system was not booted with systemd, so there is no service manager for systemctl to talk to
+    NotSystemd,
+    /// The new name given to rename cannot be used for a unit
+    InvalidName(crate::InvalidServiceName),
+    /// The binary of the service does not exist, see [ServiceConfig::allow_missing_binary]
+    BinaryNotFound(PathBuf),
+    /// Permission was denied writing the unit or running systemctl, the operation must be run as root
+    NeedsElevation,
+}
+
+impl From<std::io::Error> for CreateError {
+    fn from(value: std::io::Error) -> Self {
+        if value.kind() == std::io::ErrorKind::PermissionDenied {
+            Self::NeedsElevation
+        } else {
+            Self::FileIoError(value)
+        }
+    }
 }
 
 impl From<StartStopError> for CreateError {
     fn from(value: StartStopError) -> Self {
         match value {
             StartStopError::NoSystemCtl => Self::NoSystemCtl,
-            StartStopError::SystemCtlFailed => Self::SystemCtlFailed,
+            StartStopError::NotSystemd => Self::NotSystemd,
+            StartStopError::NeedsElevation => Self::NeedsElevation,
+            StartStopError::SystemCtlFailed { code, stderr } => {
+                Self::SystemCtlFailed { code, stderr }
+            }
+            StartStopError::StopTimeout => Self::SystemCtlFailed {
+                code: None,
+                stderr: "the service did not stop within the stop timeout".to_string(),
+            },
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 /// A placeholder, not currently used
 pub struct Session(String);
 
+/// Which instance of systemd manages the service
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// The system wide service manager, this usually requires root
+    System,
+    /// The service manager of the current user, run with systemctl --user
+    User,
+}
+
+/// The conditions under which systemd restarts the service
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RestartPolicy {
+    /// The service is never restarted
+    No,
+    /// The service is restarted when it exits with an error or is killed
+    OnFailure,
+    /// The service is always restarted
+    Always,
+    /// The service is restarted when killed by a signal or a timeout
+    OnAbnormal,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self::No
+    }
+}
+
+impl RestartPolicy {
+    /// The value used for the Restart= directive
+    fn directive(&self) -> &'static str {
+        match self {
+            RestartPolicy::No => "no",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Always => "always",
+            RestartPolicy::OnAbnormal => "on-abnormal",
+        }
+    }
+
+    /// Parse the value of a Restart= directive, unknown values are treated as no
+    fn from_directive(value: &str) -> Self {
+        match value {
+            "on-failure" => RestartPolicy::OnFailure,
+            "always" => RestartPolicy::Always,
+            "on-abnormal" => RestartPolicy::OnAbnormal,
+            _ => RestartPolicy::No,
+        }
+    }
+}
+
+/// Which processes of the service systemd sends the stop signal to
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KillMode {
+    /// Every process in the control group of the service
+    ControlGroup,
+    /// The main process first, then the rest of the control group
+    Mixed,
+    /// Only the main process
+    Process,
+    /// No process, only the stop command is run
+    None,
+}
+
+impl KillMode {
+    /// The value used for the KillMode= directive
+    fn directive(&self) -> &'static str {
+        match self {
+            KillMode::ControlGroup => "control-group",
+            KillMode::Mixed => "mixed",
+            KillMode::Process => "process",
+            KillMode::None => "none",
+        }
+    }
+
+    /// Parse the value of a KillMode= directive
+    fn from_directive(value: &str) -> Option<Self> {
+        match value {
+            "control-group" => Some(KillMode::ControlGroup),
+            "mixed" => Some(KillMode::Mixed),
+            "process" => Some(KillMode::Process),
+            "none" => Some(KillMode::None),
+            _ => None,
+        }
+    }
+}
+
+/// Where systemd sends the standard output or standard error of the service
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutputTarget {
+    /// The systemd journal
+    Journal,
+    /// Discard the output
+    Null,
+    /// The same place as standard input, or standard output for standard error
+    Inherit,
+    /// Overwrite the file at the absolute path
+    File(PathBuf),
+    /// Append to the file at the absolute path
+    Append(PathBuf),
+}
+
+impl OutputTarget {
+    /// The value used for the StandardOutput= and StandardError= directives
+    fn directive(&self) -> String {
+        match self {
+            OutputTarget::Journal => "journal".to_string(),
+            OutputTarget::Null => "null".to_string(),
+            OutputTarget::Inherit => "inherit".to_string(),
+            OutputTarget::File(p) => format!("file:{}", p.display()),
+            OutputTarget::Append(p) => format!("append:{}", p.display()),
+        }
+    }
+
+    /// Parse the value of a StandardOutput= or StandardError= directive
+    fn from_directive(value: &str) -> Option<Self> {
+        if let Some(p) = value.strip_prefix("file:") {
+            return Some(OutputTarget::File(PathBuf::from(p)));
+        }
+        if let Some(p) = value.strip_prefix("append:") {
+            return Some(OutputTarget::Append(PathBuf::from(p)));
+        }
+        match value {
+            "journal" => Some(OutputTarget::Journal),
+            "null" => Some(OutputTarget::Null),
+            "inherit" => Some(OutputTarget::Inherit),
+            _ => None,
+        }
+    }
+
+    /// The path of the file for the file targets
+    fn path(&self) -> Option<&PathBuf> {
+        match self {
+            OutputTarget::File(p) | OutputTarget::Append(p) => Some(p),
+            _ => None,
+        }
+    }
+}
+
+/// A check that systemd makes before starting the service
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConditionCheck {
+    /// The absolute path exists
+    PathExists(PathBuf),
+    /// The absolute path exists and is a directory
+    PathIsDirectory(PathBuf),
+    /// The absolute path is a regular file that is not empty
+    FileNotEmpty(PathBuf),
+    /// The hostname or machine id of the system matches
+    Host(String),
+}
+
+impl ConditionCheck {
+    /// The name of the check as used after the Condition or Assert prefix of the directive
+    fn name(&self) -> &'static str {
+        match self {
+            ConditionCheck::PathExists(_) => "PathExists",
+            ConditionCheck::PathIsDirectory(_) => "PathIsDirectory",
+            ConditionCheck::FileNotEmpty(_) => "FileNotEmpty",
+            ConditionCheck::Host(_) => "Host",
+        }
+    }
+
+    /// The value of the check, without negation
+    fn value(&self) -> String {
+        match self {
+            ConditionCheck::PathExists(p)
+            | ConditionCheck::PathIsDirectory(p)
+            | ConditionCheck::FileNotEmpty(p) => p.display().to_string(),
+            ConditionCheck::Host(h) => h.clone(),
+        }
+    }
+
+    /// Build a check from its name and value
+    fn from_name(name: &str, value: &str) -> Option<Self> {
+        match name {
+            "PathExists" => Some(ConditionCheck::PathExists(PathBuf::from(value))),
+            "PathIsDirectory" => Some(ConditionCheck::PathIsDirectory(PathBuf::from(value))),
+            "FileNotEmpty" => Some(ConditionCheck::FileNotEmpty(PathBuf::from(value))),
+            "Host" => Some(ConditionCheck::Host(value.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// A condition in the unit section of the service.
+/// A failed condition skips starting the service quietly, a failed assertion makes the start fail with an error
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Condition {
+    /// The check that is made
+    pub check: ConditionCheck,
+    /// The condition holds when the check fails
+    pub negate: bool,
+    /// Write an Assert directive instead of a Condition directive
+    pub assert: bool,
+}
+
+impl Condition {
+    /// A condition that holds when the check passes
+    pub fn new(check: ConditionCheck) -> Self {
+        Self {
+            check,
+            negate: false,
+            assert: false,
+        }
+    }
+
+    /// The directive line for the condition, such as `ConditionPathExists=!/etc/app.conf`
+    fn directive(&self) -> String {
+        format!(
+            "{}{}={}{}",
+            if self.assert { "Assert" } else { "Condition" },
+            self.check.name(),
+            if self.negate { "!" } else { "" },
+            self.check.value()
+        )
+    }
+
+    /// Parse a Condition or Assert directive, none for checks that are not covered by [ConditionCheck]
+    fn from_directive(key: &str, value: &str) -> Option<Self> {
+        let (assert, name) = match key.strip_prefix("Assert") {
+            Some(name) => (true, name),
+            None => (false, key.strip_prefix("Condition")?),
+        };
+        let (negate, value) = match value.strip_prefix('!') {
+            Some(value) => (true, value),
+            None => (false, value),
+        };
+        Some(Self {
+            check: ConditionCheck::from_name(name, value)?,
+            negate,
+            assert,
+        })
+    }
+}
+
+/// How systemd decides that the service has finished starting
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ServiceType {
+    /// The service is considered started as soon as the process is running
+    Simple,
+    /// The service calls `Service::notify_ready` of the notify feature once it has finished starting
+    Notify,
+    /// The process forks a daemon and exits, the daemon is tracked through the pid file of the config
+    Forking,
+    /// The service runs to completion once, it is considered started when the process exits
+    Oneshot,
+}
+
+impl Default for ServiceType {
+    fn default() -> Self {
+        Self::Simple
+    }
+}
+
+/// The unit names for a list of services, names without a unit suffix are treated as `.service` units
+fn unit_names(names: &[String]) -> Vec<String> {
+    names
+        .iter()
+        .map(|d| {
+            if d.contains('.') {
+                d.clone()
+            } else {
+                format!("{}.service", d)
+            }
+        })
+        .collect()
+}
+
+/// When a systemd timer activates its service
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimerSchedule {
+    /// A calendar event for OnCalendar=, such as `daily` or `*-*-* 02:00:00`
+    Calendar(String),
+    /// Run the service this long after boot and then this long after each activation, using OnBootSec= and OnUnitActiveSec=
+    Interval(std::time::Duration),
+}
+
+/// The address that a systemd socket listens on
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ListenAddress {
+    /// A port on all addresses
+    Port(u16),
+    /// An absolute path for a unix socket
+    Path(PathBuf),
+    /// A `host:port` pair, such as `127.0.0.1:8080` or `[::1]:8080`
+    HostPort(String),
+}
+
+impl ListenAddress {
+    /// The address as written in a socket unit
+    fn directive_value(&self) -> Result<String, CreateError> {
+        match self {
+            ListenAddress::Port(port) => Ok(port.to_string()),
+            ListenAddress::Path(path) => {
+                if !path.is_absolute() {
+                    return Err(CreateError::InvalidConfig(format!(
+                        "the socket path {} is not absolute",
+                        path.display()
+                    )));
+                }
+                Ok(path.display().to_string())
+            }
+            ListenAddress::HostPort(hp) => {
+                let valid = hp
+                    .rsplit_once(':')
+                    .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+                if !valid {
+                    return Err(CreateError::InvalidConfig(format!(
+                        "the socket address {} is not host:port",
+                        hp
+                    )));
+                }
+                Ok(hp.clone())
+            }
+        }
+    }
+}
+
+/// What a systemd socket listens for, the service is started when it receives a connection or datagram
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SocketSpec {
+    /// A stream socket, using ListenStream=
+    Stream(ListenAddress),
+    /// A datagram socket, using ListenDatagram=
+    Datagram(ListenAddress),
+}
+
+/// Which parts of the file system systemd mounts read only for the service
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProtectSystem {
+    /// /usr and the boot loader directories
+    Yes,
+    /// /usr, the boot loader directories and /etc
+    Full,
+    /// The whole file system, except /dev, /proc, /sys and the read write paths of the sandbox
+    Strict,
+}
+
+impl ProtectSystem {
+    /// The value used for the ProtectSystem= directive
+    fn directive(&self) -> &'static str {
+        match self {
+            ProtectSystem::Yes => "true",
+            ProtectSystem::Full => "full",
+            ProtectSystem::Strict => "strict",
+        }
+    }
+
+    /// Parse the value of a ProtectSystem= directive
+    fn from_directive(value: &str) -> Option<Self> {
+        match value {
+            "true" | "yes" => Some(ProtectSystem::Yes),
+            "full" => Some(ProtectSystem::Full),
+            "strict" => Some(ProtectSystem::Strict),
+            _ => None,
+        }
+    }
+}
+
+/// The systemd sandboxing settings of a service, everything is off by default
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SandboxOptions {
+    /// Which parts of the file system are read only for the service
+    pub protect_system: Option<ProtectSystem>,
+    /// Make /home, /root and /run/user inaccessible to the service
+    pub protect_home: bool,
+    /// Give the service its own /tmp and /var/tmp
+    pub private_tmp: bool,
+    /// Prevent the service and its children from gaining privileges, such as through setuid binaries
+    pub no_new_privileges: bool,
+    /// Absolute paths the service may still write to when they are made read only by protect_system
+    pub read_write_paths: Vec<PathBuf>,
+}
+
+impl SandboxOptions {
+    /// The common hardened settings, a strict read only file system with private temporary directories, no access to home directories, and no new privileges
+    pub fn hardened() -> Self {
+        Self {
+            protect_system: Some(ProtectSystem::Strict),
+            protect_home: true,
+            private_tmp: true,
+            no_new_privileges: true,
+            read_write_paths: Vec::new(),
+        }
+    }
+}
+
+/// Parse the value of a boolean directive of systemd
+fn parse_unit_bool(value: &str) -> bool {
+    matches!(value, "true" | "yes" | "on" | "1")
+}
+
 /// The configuration for constructing a Service.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct ServiceConfig {
     /// The arguments for the service
     arguments: Vec<String>,
@@ -81,8 +546,83 @@ pub struct ServiceConfig {
     binary: PathBuf,
     /// The username that the service should run as
     username: Option<String>,
-    /// The path to the configuration data for the service
+    /// The path to the configuration data for the service, used as its working directory. When empty, [Service::default_config_path] is used,
+    /// which systemd creates as the state directory of a system service. A path that is set is created by create
     pub config_path: PathBuf,
+    /// Allow creating the service before its binary exists, for staged installs where the binary is put in place afterwards
+    pub allow_missing_binary: bool,
+    /// When the service should be restarted by systemd
+    pub restart_policy: RestartPolicy,
+    /// How long systemd waits before restarting the service
+    pub restart_sec: Option<std::time::Duration>,
+    /// The environment variables provided to the service
+    pub environment: Vec<(String, String)>,
+    /// The command systemd runs to reload the service, such as `/bin/kill -HUP $MAINPID`
+    pub reload_command: Option<String>,
+    /// How long systemd waits for the service to stop before killing it
+    pub stop_timeout: Option<std::time::Duration>,
+    /// How systemd decides that the service has finished starting
+    pub service_type: ServiceType,
+    /// The file that a forking service writes the process id of its daemon to
+    pub pid_file: Option<PathBuf>,
+    /// How long systemd waits for a watchdog ping before restarting the service
+    pub watchdog: Option<std::time::Duration>,
+    /// The group that the service should run as
+    pub group: Option<String>,
+    /// Directories below /run that systemd creates for the service, owned by its user and group, and removes when it stops
+    pub runtime_directory: Vec<String>,
+    /// Directories below /var/lib that systemd creates for the service, owned by its user and group
+    pub state_directory: Vec<String>,
+    /// Commands run before the service starts, each command is the program followed by its arguments
+    pub exec_start_pre: Vec<Vec<String>>,
+    /// Commands run after the service stops, each command is the program followed by its arguments
+    pub exec_stop_post: Vec<Vec<String>>,
+    /// The maximum number of open files for the service
+    pub limit_nofile: Option<u64>,
+    /// The memory limit for the service, such as `512M`, `2G`, `50%` or `infinity`
+    pub memory_max: Option<String>,
+    /// The scheduling priority of the service, from -20 for the highest priority to 19 for the lowest
+    pub nice: Option<i32>,
+    /// How likely the kernel is to kill the service when out of memory, from -1000 for never to 1000 for first
+    pub oom_score_adjust: Option<i32>,
+    /// The share of cpu time the service may use as a percentage, such as `20%`, or `200%` for two cpus
+    pub cpu_quota: Option<String>,
+    /// The cpus the processes of the service are pinned to, a list such as `0-3,5`
+    pub cpu_affinity: Option<String>,
+    /// The cpus the control group of the service may use, a list such as `0-3,5`
+    pub allowed_cpus: Option<String>,
+    /// The slice the control group of the service is placed in, such as `latency.slice`
+    pub slice: Option<String>,
+    /// The target that wants the service when it is enabled, none leaves out the install section. User services use default.target in place of multi-user.target
+    pub install_target: Option<String>,
+    /// The service is only started by its socket unit, see [Service::create_socket]. It must then have no install target
+    pub socket_activated: bool,
+    /// Other units that are enabled and disabled along with the service
+    pub also: Vec<String>,
+    /// Which processes of the service systemd sends the stop signal to
+    pub kill_mode: Option<KillMode>,
+    /// The signal systemd uses to stop the service, such as `SIGINT`
+    pub kill_signal: Option<String>,
+    /// Where the standard output of the service goes
+    pub standard_output: Option<OutputTarget>,
+    /// Where the standard error of the service goes
+    pub standard_error: Option<OutputTarget>,
+    /// Other services that must be started before this one, names without a unit suffix are treated as `.service` units
+    pub dependencies: Vec<String>,
+    /// Other services that are started along with this one, without it failing when they fail. Names without a unit suffix are treated as `.service` units
+    pub wants: Vec<String>,
+    /// Uris of the documentation for the service, such as `https://example.com/docs` or `man:example(8)`, shown by `systemctl status`
+    pub documentation: Vec<String>,
+    /// Checks that must pass before systemd starts the service
+    pub conditions: Vec<Condition>,
+    /// The systemd sandboxing settings of the service
+    pub sandbox: SandboxOptions,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self::new(Vec::new(), String::new(), PathBuf::new(), None)
+    }
 }
 
 impl ServiceConfig {
@@ -105,8 +645,440 @@ impl ServiceConfig {
             description,
             binary,
             config_path: PathBuf::new(),
+            allow_missing_binary: false,
             username,
+            restart_policy: RestartPolicy::default(),
+            restart_sec: None,
+            environment: Vec::new(),
+            reload_command: None,
+            dependencies: Vec::new(),
+            wants: Vec::new(),
+            documentation: Vec::new(),
+            stop_timeout: None,
+            service_type: ServiceType::default(),
+            pid_file: None,
+            watchdog: None,
+            group: None,
+            runtime_directory: Vec::new(),
+            state_directory: Vec::new(),
+            exec_start_pre: Vec::new(),
+            exec_stop_post: Vec::new(),
+            limit_nofile: None,
+            memory_max: None,
+            nice: None,
+            oom_score_adjust: None,
+            cpu_quota: None,
+            cpu_affinity: None,
+            allowed_cpus: None,
+            slice: None,
+            install_target: Some("multi-user.target".to_string()),
+            socket_activated: false,
+            also: Vec::new(),
+            kill_mode: None,
+            kill_signal: None,
+            standard_output: None,
+            standard_error: None,
+            conditions: Vec::new(),
+            sandbox: SandboxOptions::default(),
+        }
+    }
+
+    /// The binary as a canonical absolute path, with symlinks and `..` resolved.
+    /// An error is returned when the binary does not exist, unless allow_missing is true, then a relative path is only joined to the current directory
+    fn absolute_binary(&self, allow_missing: bool) -> Result<PathBuf, CreateError> {
+        let binary = if self.binary.is_relative() {
+            std::env::current_dir()
+                .map_err(CreateError::from)?
+                .join(&self.binary)
+        } else {
+            self.binary.clone()
+        };
+        match std::fs::canonicalize(&binary) {
+            Ok(canonical) if canonical.is_file() => Ok(canonical),
+            _ if allow_missing => Ok(binary),
+            _ => Err(CreateError::BinaryNotFound(binary)),
+        }
+    }
+
+    /// The arguments for the service
+    pub fn arguments(&self) -> &[String] {
+        &self.arguments
+    }
+
+    /// The description of the service
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// The path to the binary that runs the service
+    pub fn binary(&self) -> &std::path::Path {
+        &self.binary
+    }
+
+    /// The username the service runs as
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    /// Check that the config can be turned into a valid unit file, warning about settings that are valid but likely a mistake
+    pub fn validate(&self) -> Result<(), CreateError> {
+        if matches!(self.service_type, ServiceType::Forking) && self.pid_file.is_none() {
+            log::warn!(
+                "The forking service {} has no pid file, systemd has to guess the main process",
+                self.binary.display()
+            );
+        }
+        for uri in &self.documentation {
+            if uri.is_empty() || uri.contains(char::is_whitespace) || !uri.contains(':') {
+                return Err(CreateError::InvalidConfig(format!(
+                    "documentation {:?} is not a uri",
+                    uri
+                )));
+            }
+        }
+        if self.socket_activated && self.install_target.is_some() {
+            return Err(CreateError::InvalidConfig(
+                "a socket activated service must not have an install target".to_string(),
+            ));
+        }
+        if !self.config_path.as_os_str().is_empty() && !self.config_path.is_absolute() {
+            return Err(CreateError::InvalidConfig(format!(
+                "config_path {} must be an absolute path",
+                self.config_path.display()
+            )));
+        }
+        if let Some(memory) = &self.memory_max {
+            if !is_systemd_size(memory) {
+                return Err(CreateError::InvalidConfig(format!(
+                    "memory_max {} is not a valid size",
+                    memory
+                )));
+            }
+        }
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                return Err(CreateError::InvalidConfig(format!(
+                    "nice {} must be between -20 and 19",
+                    nice
+                )));
+            }
+        }
+        if let Some(adjust) = self.oom_score_adjust {
+            if !(-1000..=1000).contains(&adjust) {
+                return Err(CreateError::InvalidConfig(format!(
+                    "oom_score_adjust {} must be between -1000 and 1000",
+                    adjust
+                )));
+            }
+        }
+        if let Some(quota) = &self.cpu_quota {
+            let valid = quota
+                .strip_suffix('%')
+                .and_then(|q| q.parse::<u32>().ok())
+                .map(|q| q > 0)
+                .unwrap_or(false);
+            if !valid {
+                return Err(CreateError::InvalidConfig(format!(
+                    "cpu_quota {} must be a percentage such as 20%",
+                    quota
+                )));
+            }
+        }
+        for (name, cpus) in [
+            ("cpu_affinity", &self.cpu_affinity),
+            ("allowed_cpus", &self.allowed_cpus),
+        ] {
+            if let Some(cpus) = cpus {
+                if !is_cpu_list(cpus) {
+                    return Err(CreateError::InvalidConfig(format!(
+                        "{} {} must be a cpu list such as 0-3,5",
+                        name, cpus
+                    )));
+                }
+            }
+        }
+        if let Some(slice) = &self.slice {
+            let valid = slice
+                .strip_suffix(".slice")
+                .map(|s| validate_name(s).is_ok())
+                .unwrap_or(false);
+            if !valid {
+                return Err(CreateError::InvalidConfig(format!(
+                    "slice {} must be a unit name ending in .slice",
+                    slice
+                )));
+            }
+        }
+        for dir in self.runtime_directory.iter().chain(&self.state_directory) {
+            let path = std::path::Path::new(dir);
+            let relative = path
+                .components()
+                .all(|c| matches!(c, std::path::Component::Normal(_)));
+            if dir.is_empty() || !relative {
+                return Err(CreateError::InvalidConfig(format!(
+                    "directory {} must be a relative path without ..",
+                    dir
+                )));
+            }
+        }
+        for path in &self.sandbox.read_write_paths {
+            if !path.is_absolute() || path.to_string_lossy().contains(char::is_whitespace) {
+                return Err(CreateError::InvalidConfig(format!(
+                    "read write path {} must be an absolute path without whitespace",
+                    path.display()
+                )));
+            }
+        }
+        for condition in &self.conditions {
+            match &condition.check {
+                ConditionCheck::PathExists(p)
+                | ConditionCheck::PathIsDirectory(p)
+                | ConditionCheck::FileNotEmpty(p)
+                    if !p.is_absolute() =>
+                {
+                    return Err(CreateError::InvalidConfig(format!(
+                        "condition path {} must be an absolute path",
+                        p.display()
+                    )));
+                }
+                _ => {}
+            }
+        }
+        for target in [&self.standard_output, &self.standard_error]
+            .into_iter()
+            .flatten()
+        {
+            if let Some(p) = target.path() {
+                if !p.is_absolute() {
+                    return Err(CreateError::InvalidConfig(format!(
+                        "output file {} must be an absolute path",
+                        p.display()
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that a value follows the systemd size grammar, a number of bytes with an optional K, M, G, T, P, or E suffix, a percentage, or infinity
+fn is_systemd_size(value: &str) -> bool {
+    if value == "infinity" {
+        return true;
+    }
+    let number = value
+        .strip_suffix(['K', 'M', 'G', 'T', 'P', 'E', '%'])
+        .unwrap_or(value);
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, "0"));
+    !whole.is_empty()
+        && whole.chars().all(|c| c.is_ascii_digit())
+        && !fraction.is_empty()
+        && fraction.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Check that a value is a list of cpus, separated by commas, where each entry is a cpu number or an inclusive range such as `0-3`
+fn is_cpu_list(value: &str) -> bool {
+    let is_number = |n: &str| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit());
+    value.split(',').all(|entry| match entry.split_once('-') {
+        Some((first, last)) => {
+            is_number(first)
+                && is_number(last)
+                && first.parse::<u32>().ok() <= last.parse::<u32>().ok()
+        }
+        None => is_number(entry),
+    })
+}
+
+/// Escape an environment variable assignment for an Environment= directive
+fn escape_environment(key: &str, value: &str) -> String {
+    let mut s = String::from("\"");
+    for c in key.chars().chain(std::iter::once('=')).chain(value.chars()) {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '%' => s.push_str("%%"),
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+    s
+}
+
+/// Quote a single argument of an ExecStart= or similar directive so that systemd passes it through unchanged.
+/// With template set, the %i and %I instance specifiers are kept so that systemd replaces them with the instance name
+fn escape_exec_arg(arg: &str, template: bool) -> String {
+    let needs_quotes = arg.is_empty()
+        || arg
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';'));
+    let mut s = String::new();
+    if needs_quotes {
+        s.push('"');
+    }
+    let mut chars = arg.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if needs_quotes => s.push_str("\\\""),
+            '\\' if needs_quotes => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '\t' => s.push_str("\\t"),
+            '%' if template && matches!(chars.peek(), Some('i' | 'I')) => s.push('%'),
+            '%' => s.push_str("%%"),
+            '$' => s.push_str("$$"),
+            c => s.push(c),
+        }
+    }
+    if needs_quotes {
+        s.push('"');
+    }
+    s
+}
+
+/// An XDG base directory from the value of its variable, or fallback below home.
+/// A relative or empty value is ignored as the XDG spec requires, the result is still relative when home is not set
+fn xdg_dir(
+    value: Option<std::ffi::OsString>,
+    home: Option<std::ffi::OsString>,
+    fallback: &str,
+) -> PathBuf {
+    value
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(|| PathBuf::from(home.unwrap_or_default()).join(fallback))
+}
+
+/// Escape each argument of a command for an ExecStartPre= or similar directive, the same way as for ExecStart=
+fn escape_exec_command(command: &[String], template: bool) -> String {
+    command
+        .iter()
+        .map(|arg| escape_exec_arg(arg, template))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Split the value of an ExecStart= or Environment= directive into words, undoing the quoting of escape_exec_arg and escape_environment
+fn split_unit_words(value: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut chars = value.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
         }
+        let quoted = chars.next_if_eq(&'"').is_some();
+        let mut word = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted => break,
+                c if !quoted && c.is_whitespace() => break,
+                '\\' => match chars.next() {
+                    Some('n') => word.push('\n'),
+                    Some('t') => word.push('\t'),
+                    Some(c) => word.push(c),
+                    None => word.push('\\'),
+                },
+                '%' | '$' if chars.peek() == Some(&c) => {
+                    chars.next();
+                    word.push(c);
+                }
+                c => word.push(c),
+            }
+        }
+        words.push(word);
+    }
+    words
+}
+
+/// Parse a time span such as `1500ms`, `5s`, or `2min` as written in a unit file, a bare number is in seconds
+fn parse_unit_duration(value: &str) -> Option<std::time::Duration> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    match unit.trim() {
+        "ms" => Some(std::time::Duration::from_millis(number)),
+        "" | "s" | "sec" => Some(std::time::Duration::from_secs(number)),
+        "min" | "m" => Some(std::time::Duration::from_secs(number * 60)),
+        "h" | "hr" => Some(std::time::Duration::from_secs(number * 3600)),
+        _ => None,
+    }
+}
+
+/// The suffixes of the systemd unit types, a service name ending in one of these would be mistaken for another unit
+const UNIT_SUFFIXES: [&str; 11] = [
+    ".service",
+    ".socket",
+    ".device",
+    ".mount",
+    ".automount",
+    ".swap",
+    ".target",
+    ".path",
+    ".timer",
+    ".slice",
+    ".scope",
+];
+
+/// Check that a name can be used for a systemd unit, once the `.service` suffix is added
+fn validate_name(name: &str) -> Result<(), crate::InvalidServiceName> {
+    if name.is_empty() {
+        return Err(crate::InvalidServiceName::Empty);
+    }
+    let max = 255 - ".service".len();
+    if name.len() > max {
+        return Err(crate::InvalidServiceName::TooLong(max));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, ':' | '-' | '_' | '.' | '\\')))
+    {
+        return Err(crate::InvalidServiceName::InvalidCharacter(c));
+    }
+    if let Some(suffix) = UNIT_SUFFIXES.iter().find(|s| name.ends_with(*s)) {
+        let suffix = suffix.to_string();
+        return Err(crate::InvalidServiceName::ReservedSuffix(suffix));
+    }
+    Ok(())
+}
+
+/// Convert the output of systemctl is-active into the status of the service
+fn active_state_status(o: &std::process::Output) -> Result<crate::ServiceStatus, StartStopError> {
+    match String::from_utf8_lossy(&o.stdout).trim() {
+        "active" | "reloading" => Ok(crate::ServiceStatus::Running),
+        "activating" => Ok(crate::ServiceStatus::StartPending),
+        "deactivating" => Ok(crate::ServiceStatus::StopPending),
+        "inactive" | "failed" => Ok(crate::ServiceStatus::Stopped),
+        _ => Err(systemctl_failed(o)),
+    }
+}
+
+/// Convert the MainPID property from systemctl show into a process id, zero means there is no process
+fn main_pid(o: &std::process::Output) -> Result<Option<u32>, StartStopError> {
+    match String::from_utf8_lossy(&o.stdout).trim().parse::<u32>() {
+        Ok(0) => Ok(None),
+        Ok(pid) => Ok(Some(pid)),
+        Err(_) => Err(systemctl_failed(o)),
+    }
+}
+
+/// The messages systemctl prints when the caller lacks the privilege for the operation
+const PERMISSION_MESSAGES: [&str; 3] = [
+    "Access denied",
+    "Permission denied",
+    "Interactive authentication required",
+];
+
+/// The error for a systemctl command that did not succeed, keeping the exit code and standard error of systemctl
+fn systemctl_failed(o: &std::process::Output) -> StartStopError {
+    let stderr = String::from_utf8_lossy(&o.stderr).trim().to_string();
+    if PERMISSION_MESSAGES.iter().any(|m| stderr.contains(m)) {
+        return StartStopError::NeedsElevation;
+    }
+    StartStopError::SystemCtlFailed {
+        code: o.status.code(),
+        stderr,
     }
 }
 
@@ -114,142 +1086,1727 @@ impl ServiceConfig {
 pub struct Service {
     /// The name of the service, as known by the operating system
     name: String,
+    /// Whether the service is managed by the system or by the user's own service manager
+    scope: Scope,
+    /// How long stop waits for the service to stop, None waits as long as systemctl does
+    stop_timeout: Option<std::time::Duration>,
+    /// The systemctl binary, None finds systemctl on the path
+    systemctl_path: Option<PathBuf>,
+    /// The directory the unit files are written to, None uses the directory for the scope
+    unit_dir: Option<PathBuf>,
+}
+
+/// The error for a systemctl that cannot be run, telling apart a system that does not use systemd
+fn systemctl_missing() -> StartStopError {
+    if Service::is_systemd_running() {
+        StartStopError::NoSystemCtl
+    } else {
+        StartStopError::NotSystemd
+    }
 }
 
 impl Service {
-    /// Construct a new self
+    /// Construct a new self, this panics when the name is not a valid unit name, see [Service::try_new]
     pub fn new(name: String) -> Self {
-        Self { name }
+        Self::try_new(&name).unwrap()
     }
 
-    /// Initialize a new log instance
-    pub fn new_log(&self, level: super::LogLevel) {
-        simple_logger::SimpleLogger::new().init().unwrap();
-        log::set_max_level(level.level_filter());
+    /// Construct a new self, checking that the name is a valid systemd unit name
+    pub fn try_new(name: &str) -> Result<Self, crate::InvalidServiceName> {
+        validate_name(name)?;
+        Ok(Self {
+            name: name.to_string(),
+            scope: Scope::System,
+            stop_timeout: None,
+            systemctl_path: None,
+            unit_dir: None,
+        })
     }
 
-    /// The systemd path for linux
-    pub fn systemd_path(&self) -> PathBuf {
-        PathBuf::from("/etc/systemd/system")
+    /// Construct a new self for a service that runs under the service manager of the current user, this panics when the name is not a valid unit name, see [Service::try_new_user]
+    pub fn new_user(name: String) -> Self {
+        Self::try_new_user(&name).unwrap()
     }
 
-    /// Does the service already exist?
-    pub fn exists(&self) -> bool {
-        let systemd_path = self.systemd_path();
-        let pb = systemd_path.join(format!("{}.service", self.name));
-        pb.exists()
+    /// Construct a new self for a service of the current user, checking that the name is a valid systemd unit name
+    pub fn try_new_user(name: &str) -> Result<Self, crate::InvalidServiceName> {
+        validate_name(name)?;
+        Ok(Self {
+            name: name.to_string(),
+            scope: Scope::User,
+            stop_timeout: None,
+            systemctl_path: None,
+            unit_dir: None,
+        })
     }
 
-    /// Stop the service
-    pub fn stop(&mut self) -> Result<(), StartStopError> {
-        let o = std::process::Command::new("systemctl")
-            .arg("stop")
-            .arg(&self.name)
-            .output()
-            .map_err(|_| StartStopError::NoSystemCtl)?;
-        if !o.status.success() {
-            Err(StartStopError::SystemCtlFailed)
-        } else {
-            Ok(())
-        }
+    /// Returns true when the current process was started by systemd as a service, instead of from a terminal
+    pub fn is_launched_as_service() -> bool {
+        std::env::var_os("INVOCATION_ID").is_some() || std::env::var_os("JOURNAL_STREAM").is_some()
     }
 
-    /// Start the service
-    pub fn start(&mut self) -> Result<(), StartStopError> {
-        let o = std::process::Command::new("systemctl")
-            .arg("start")
-            .arg(&self.name)
-            .output()
-            .map_err(|_| StartStopError::NoSystemCtl)?;
-        if !o.status.success() {
-            Err(StartStopError::SystemCtlFailed)
-        } else {
-            Ok(())
+    /// The name of the service
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The scope that the service is managed in
+    pub fn scope(&self) -> Scope {
+        self.scope
+    }
+
+    /// Give the service a new name by moving its unit file, then reload systemd.
+    /// The service should be stopped and disabled first, since systemd still knows the running service and the links made by enable under the old name
+    pub fn rename(&mut self, name: &str) -> Result<(), CreateError> {
+        validate_name(name).map_err(CreateError::InvalidName)?;
+        let old = self.systemd_path().join(format!("{}.service", self.name));
+        let new = self.systemd_path().join(format!("{}.service", name));
+        if new.exists() {
+            return Err(CreateError::FileIoError(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already exists", new.display()),
+            )));
         }
+        log::info!("Moving {} to {}", old.display(), new.display());
+        std::fs::rename(old, new).map_err(CreateError::from)?;
+        self.name = name.to_string();
+        Ok(self.reload()?)
     }
 
-    /// Delete the service
-    pub fn delete(&mut self) -> Result<(), std::io::Error> {
-        let pb = self.systemd_path().join(format!("{}.service", self.name));
-        println!("Deleting {}", pb.display());
-        std::fs::remove_file(pb)
+    /// The config path used when the config does not set one, `/var/lib/<name>` for a system service.
+    /// A user service uses `<name>` in `$XDG_STATE_HOME`, or in `~/.local/state` when that is not set or not absolute
+    pub fn default_config_path(&self) -> PathBuf {
+        match self.scope {
+            Scope::System => PathBuf::from("/var/lib").join(&self.name),
+            Scope::User => {
+                let state = xdg_dir(
+                    std::env::var_os("XDG_STATE_HOME"),
+                    std::env::var_os("HOME"),
+                    ".local/state",
+                );
+                state.join(&self.name)
+            }
+        }
     }
 
-    #[cfg(feature = "async")]
-    /// Delete the service
-    pub async fn delete_async(&mut self) -> Result<(), std::io::Error> {
-        let pb = self.systemd_path().join(format!("{}.service", self.name));
-        println!("Deleting {}", pb.display());
-        tokio::fs::remove_file(pb).await
+    /// The config path of the service, as written in its unit file, or the default when the service does not exist
+    pub fn config_path(&self) -> PathBuf {
+        match self.query_config() {
+            Ok(config) if !config.config_path.as_os_str().is_empty() => config.config_path,
+            _ => self.default_config_path(),
+        }
     }
 
-    /// Reload system services if required
-    fn reload(&mut self) -> Result<(), StartStopError> {
-        let o = std::process::Command::new("systemctl")
-            .arg("daemon-reload")
-            .output()
-            .map_err(|_| StartStopError::NoSystemCtl)?;
-        if !o.status.success() {
-            Err(StartStopError::SystemCtlFailed)
+    /// The config path of the config, or the default when it does not set one
+    fn resolved_config_path(&self, config: &ServiceConfig) -> PathBuf {
+        if config.config_path.as_os_str().is_empty() {
+            self.default_config_path()
         } else {
-            Ok(())
+            config.config_path.clone()
         }
     }
 
-    /// Construct the systemd file with the specified config
-    fn build_systemd_file(&self, config: ServiceConfig) -> String {
-        let mut con = String::new();
-        con.push_str("[Unit]\n");
-        con.push_str(&format!("Description={}\n", config.description));
-        con.push_str("[Service]\n");
-        if let Some(user) = config.username {
-            con.push_str(&format!("User={}\n", user));
+    /// Systemd creates the default config path of a system service as its state directory, owned by the user of the service
+    fn default_state_directory(&self, config: &ServiceConfig) -> bool {
+        self.scope == Scope::System && config.config_path.as_os_str().is_empty()
+    }
+
+    /// The state directories of the unit, including the default config path of a system service
+    fn state_directories(&self, config: &ServiceConfig) -> Vec<String> {
+        let mut dirs = config.state_directory.clone();
+        if self.default_state_directory(config) && !dirs.contains(&self.name) {
+            dirs.push(self.name.clone());
         }
-        con.push_str(&format!(
-            "WorkingDirectory={}\n",
-            config.config_path.display()
-        ));
-        con.push_str(&format!(
-            "ExecStart={} {}\n",
-            config.binary.display(),
-            config.arguments.join(" ")
-        ));
-        con.push_str("\n[Install]\nWantedBy=multi-user.target\n");
-        con
+        dirs
     }
 
-    /// Create the service
-    pub fn create(&mut self, config: ServiceConfig) -> Result<(), CreateError> {
-        use std::io::Write;
-        let con = self.build_systemd_file(config);
-        let pb = self.systemd_path().join(format!("{}.service", self.name));
-        println!("Saving service file as {}", pb.display());
-        let mut fpw = std::fs::File::create(pb).map_err(CreateError::FileIoError)?;
-        fpw.write_all(con.as_bytes())
-            .map_err(CreateError::FileIoError)?;
-        Ok(self.reload()?)
+    /// Check the config with [ServiceConfig::validate], and that the working directory it resolves to for this service is absolute.
+    /// The default working directory of a user service is relative when HOME is not set and XDG_STATE_HOME is not an absolute path
+    fn validate_config(&self, config: &ServiceConfig) -> Result<(), CreateError> {
+        config.validate()?;
+        let path = self.resolved_config_path(config);
+        if !path.is_absolute() {
+            return Err(CreateError::InvalidConfig(format!(
+                "the working directory {} must be an absolute path, set config_path",
+                path.display()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Set how long stop waits for the service to stop before returning an error
+    pub fn set_stop_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.stop_timeout = timeout;
+    }
+
+    /// Use a systemctl binary at a nonstandard location, None finds systemctl on the path
+    pub fn set_systemctl_path(&mut self, path: Option<PathBuf>) {
+        self.systemctl_path = path;
+    }
+
+    /// Set the directory that create, exists, and delete use for the unit files, such as a staging root for building a package or `/usr/lib/systemd/system`.
+    /// None uses the directory for the scope of the service. Systemd is not reloaded and the config path is not created when the directory is not one that systemd loads units from
+    pub fn set_unit_dir(&mut self, dir: Option<PathBuf>) {
+        self.unit_dir = dir;
+    }
+
+    /// Returns true when systemd loads units from the unit directory of the service
+    fn is_live_unit_dir(&self) -> bool {
+        let Some(dir) = &self.unit_dir else {
+            return true;
+        };
+        let live: &[&str] = match self.scope {
+            Scope::System => &[
+                "/etc/systemd/system",
+                "/run/systemd/system",
+                "/usr/local/lib/systemd/system",
+                "/usr/lib/systemd/system",
+                "/lib/systemd/system",
+            ],
+            Scope::User => &["/etc/systemd/user", "/usr/lib/systemd/user"],
+        };
+        live.iter().any(|l| dir == std::path::Path::new(l)) || *dir == self.default_unit_dir()
+    }
+
+    /// Returns true when the system was booted with systemd as its service manager
+    pub fn is_systemd_running() -> bool {
+        std::path::Path::new("/run/systemd/system").is_dir()
+    }
+
+    /// Build a systemctl command for the scope of the service
+    fn systemctl(&self) -> std::process::Command {
+        let systemctl = self.systemctl_path.as_deref();
+        let mut c = std::process::Command::new(systemctl.unwrap_or("systemctl".as_ref()));
+        if let Scope::User = self.scope {
+            c.arg("--user");
+        }
+        c
+    }
+
+    #[cfg(feature = "tracing")]
+    /// Initialize a new tracing subscriber, this logs to journald when the journald feature is enabled.
+    /// Records from the log crate are forwarded to the subscriber
+    pub fn new_log(&self, level: super::LogLevel) -> Result<(), super::LogInitError> {
+        use tracing_subscriber::layer::SubscriberExt;
+        use tracing_subscriber::util::SubscriberInitExt;
+        let registry = tracing_subscriber::registry().with(level.tracing_level_filter());
+        #[cfg(feature = "journald")]
+        let registry = registry.with(
+            tracing_journald::layer()
+                .map_err(|e| super::LogInitError::BackendFailed(e.to_string()))?
+                .with_syslog_identifier(self.name.clone())
+                .with_custom_fields([("SERVICE_NAME", &self.name)]),
+        );
+        #[cfg(not(feature = "journald"))]
+        let registry = registry.with(tracing_subscriber::fmt::layer());
+        registry
+            .try_init()
+            .map_err(|_| super::LogInitError::AlreadyInitialized)
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    /// Initialize a new log instance, this logs to journald when the journald feature is enabled
+    pub fn new_log(&self, level: super::LogLevel) -> Result<(), super::LogInitError> {
+        #[cfg(feature = "journald")]
+        systemd_journal_logger::JournalLog::new()
+            .map_err(|e| super::LogInitError::BackendFailed(e.to_string()))?
+            .with_syslog_identifier(self.name.clone())
+            .add_extra_field("SERVICE_NAME", &self.name)
+            .install()?;
+        #[cfg(not(feature = "journald"))]
+        simple_logger::SimpleLogger::new().init()?;
+        log::set_max_level(level.level_filter());
+        Ok(())
+    }
+
+    /// The directory of the unit files of the service, set with [Service::set_unit_dir] or depending on the scope of the service
+    pub fn systemd_path(&self) -> PathBuf {
+        match &self.unit_dir {
+            Some(dir) => dir.clone(),
+            None => self.default_unit_dir(),
+        }
+    }
+
+    /// The directory of the unit files for the scope of the service
+    fn default_unit_dir(&self) -> PathBuf {
+        match self.scope {
+            Scope::System => PathBuf::from("/etc/systemd/system"),
+            Scope::User => {
+                let config = xdg_dir(
+                    std::env::var_os("XDG_CONFIG_HOME"),
+                    std::env::var_os("HOME"),
+                    ".config",
+                );
+                config.join("systemd").join("user")
+            }
+        }
+    }
+
+    /// List the names of the installed services in the scope of this service, optionally only those starting with prefix.
+    /// This runs the systemctl set with [Service::set_systemctl_path]
+    pub fn list(&self, prefix: Option<&str>) -> Result<Vec<String>, StartStopError> {
+        let o = self
+            .systemctl()
+            .arg("list-unit-files")
+            .arg("--type=service")
+            .arg("--no-legend")
+            .arg("--plain")
+            .output()
+            .map_err(|_| systemctl_missing())?;
+        if !o.status.success() {
+            return Err(systemctl_failed(&o));
+        }
+        Ok(String::from_utf8_lossy(&o.stdout)
+            .lines()
+            .filter_map(|l| l.split_whitespace().next())
+            .filter_map(|u| u.strip_suffix(".service"))
+            .filter(|n| prefix.map(|p| n.starts_with(p)).unwrap_or(true))
+            .map(|n| n.to_string())
+            .collect())
+    }
+
+    /// Does the service already exist?
+    pub fn exists(&self) -> bool {
+        let systemd_path = self.systemd_path();
+        let pb = systemd_path.join(format!("{}.service", self.name));
+        pb.exists()
+    }
+
+    /// Stop the service, stopping a service that is not running does nothing and succeeds
+    pub fn stop(&mut self) -> Result<(), StartStopError> {
+        let mut c = self.systemctl();
+        c.arg("stop");
+        if self.stop_timeout.is_some() {
+            // queue the stop job without waiting for it, the wait happens below
+            c.arg("--no-block").arg("--job-mode=replace");
+        }
+        let o = c
+            .arg(&self.name)
+            .output()
+            .map_err(|_| systemctl_missing())?;
+        if !o.status.success() {
+            return Err(systemctl_failed(&o));
+        }
+        if let Some(timeout) = self.stop_timeout {
+            let start = std::time::Instant::now();
+            loop {
+                match self.status()? {
+                    crate::ServiceStatus::Stopped | crate::ServiceStatus::NotInstalled => break,
+                    _ => {}
+                }
+                if start.elapsed() >= timeout {
+                    return Err(StartStopError::StopTimeout);
+                }
+                std::thread::sleep(std::time::Duration::from_millis(250));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    /// Stop the service, stopping a service that is not running does nothing and succeeds
+    pub async fn stop_async(&mut self) -> Result<(), StartStopError> {
+        let mut c = tokio::process::Command::from(self.systemctl());
+        c.arg("stop");
+        if self.stop_timeout.is_some() {
+            // queue the stop job without waiting for it, the wait happens below
+            c.arg("--no-block").arg("--job-mode=replace");
+        }
+        let o = c
+            .arg(&self.name)
+            .output()
+            .await
+            .map_err(|_| systemctl_missing())?;
+        if !o.status.success() {
+            return Err(systemctl_failed(&o));
+        }
+        if let Some(timeout) = self.stop_timeout {
+            let start = std::time::Instant::now();
+            loop {
+                match self.status_async().await? {
+                    crate::ServiceStatus::Stopped | crate::ServiceStatus::NotInstalled => break,
+                    _ => {}
+                }
+                if start.elapsed() >= timeout {
+                    return Err(StartStopError::StopTimeout);
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the service, starting a service that is already running does nothing and succeeds
+    pub fn start(&mut self) -> Result<(), StartStopError> {
+        let o = self
+            .systemctl()
+            .arg("start")
+            .arg(&self.name)
+            .output()
+            .map_err(|_| systemctl_missing())?;
+        if !o.status.success() {
+            Err(systemctl_failed(&o))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "async")]
+    /// Start the service, starting a service that is already running does nothing and succeeds
+    pub async fn start_async(&mut self) -> Result<(), StartStopError> {
+        let o = tokio::process::Command::from(self.systemctl())
+            .arg("start")
+            .arg(&self.name)
+            .output()
+            .await
+            .map_err(|_| systemctl_missing())?;
+        if !o.status.success() {
+            Err(systemctl_failed(&o))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Start an instance of the template unit made by create_template
+    pub fn start_instance(&mut self, instance: &str) -> Result<(), StartStopError> {
+        self.instance_command("start", instance)
+    }
+
+    /// Stop an instance of the template unit made by create_template
+    pub fn stop_instance(&mut self, instance: &str) -> Result<(), StartStopError> {
+        self.instance_command("stop", instance)
+    }
+
+    /// Run a systemctl command on an instance of the template unit
+    fn instance_command(&mut self, command: &str, instance: &str) -> Result<(), StartStopError> {
+        let o = self
+            .systemctl()
+            .arg(command)
+            .arg(format!("{}@{}.service", self.name, instance))
+            .output()
+            .map_err(|_| systemctl_missing())?;
+        if !o.status.success() {
+            Err(systemctl_failed(&o))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Query the current status of the service
+    pub fn status(&self) -> Result<crate::ServiceStatus, StartStopError> {
+        if !self.exists() {
+            return Ok(crate::ServiceStatus::NotInstalled);
+        }
+        // is-active returns a non-zero exit code for anything other than active, so only the output is checked
+        let o = self
+            .systemctl()
+            .arg("is-active")
+            .arg(&self.name)
+            .output()
+            .map_err(|_| systemctl_missing())?;
+        active_state_status(&o)
+    }
+
+    #[cfg(feature = "async")]
+    /// Query the current status of the service
+    pub async fn status_async(&self) -> Result<crate::ServiceStatus, StartStopError> {
+        if !self.exists() {
+            return Ok(crate::ServiceStatus::NotInstalled);
+        }
+        let o = tokio::process::Command::from(self.systemctl())
+            .arg("is-active")
+            .arg(&self.name)
+            .output()
+            .await
+            .map_err(|_| systemctl_missing())?;
+        active_state_status(&o)
+    }
+
+    /// Returns true when the service is running, any error is treated as not running
+    pub fn is_running(&self) -> bool {
+        self.systemctl()
+            .arg("is-active")
+            .arg("--quiet")
+            .arg(&self.name)
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false)
+    }
+
+    /// Get the process id of the main process of the service, None when the service is not running
+    pub fn pid(&self) -> Result<Option<u32>, StartStopError> {
+        let o = self
+            .systemctl()
+            .arg("show")
+            .arg("-p")
+            .arg("MainPID")
+            .arg("--value")
+            .arg(&self.name)
+            .output()
+            .map_err(|_| systemctl_missing())?;
+        if !o.status.success() {
+            return Err(systemctl_failed(&o));
+        }
+        main_pid(&o)
+    }
+
+    #[cfg(feature = "async")]
+    /// Get the process id of the main process of the service, None when the service is not running
+    pub async fn pid_async(&self) -> Result<Option<u32>, StartStopError> {
+        let o = tokio::process::Command::from(self.systemctl())
+            .arg("show")
+            .arg("-p")
+            .arg("MainPID")
+            .arg("--value")
+            .arg(&self.name)
+            .output()
+            .await
+            .map_err(|_| systemctl_missing())?;
+        if !o.status.success() {
+            return Err(systemctl_failed(&o));
+        }
+        main_pid(&o)
+    }
+
+    /// Get the most recent lines that the service logged to the journal, oldest first
+    pub fn recent_logs(&self, lines: usize) -> Result<Vec<String>, std::io::Error> {
+        let unit = match self.scope {
+            Scope::System => "--unit",
+            Scope::User => "--user-unit",
+        };
+        let o = std::process::Command::new("journalctl")
+            .arg(unit)
+            .arg(&self.name)
+            .arg("-n")
+            .arg(lines.to_string())
+            .arg("--no-pager")
+            .arg("--quiet")
+            .output()?;
+        if !o.status.success() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                String::from_utf8_lossy(&o.stderr).trim().to_string(),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&o.stdout)
+            .lines()
+            .map(|l| l.to_string())
+            .collect())
+    }
+
+    /// Enable the service so that it starts when the system boots
+    pub fn enable(&mut self) -> Result<(), StartStopError> {
+        let o = self
+            .systemctl()
+            .arg("enable")
+            .arg(&self.name)
+            .output()
+            .map_err(|_| systemctl_missing())?;
+        if !o.status.success() {
+            Err(systemctl_failed(&o))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Disable the service so that it no longer starts when the system boots
+    pub fn disable(&mut self) -> Result<(), StartStopError> {
+        let o = self
+            .systemctl()
+            .arg("disable")
+            .arg(&self.name)
+            .output()
+            .map_err(|_| systemctl_missing())?;
+        if !o.status.success() {
+            Err(systemctl_failed(&o))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Restart the service, this is done by systemd as a single operation
+    pub fn restart(&mut self) -> Result<(), StartStopError> {
+        let o = self
+            .systemctl()
+            .arg("restart")
+            .arg(&self.name)
+            .output()
+            .map_err(|_| systemctl_missing())?;
+        if !o.status.success() {
+            Err(systemctl_failed(&o))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "async")]
+    /// Restart the service
+    pub async fn restart_async(&mut self) -> Result<(), StartStopError> {
+        let o = tokio::process::Command::from(self.systemctl())
+            .arg("restart")
+            .arg(&self.name)
+            .output()
+            .await
+            .map_err(|_| systemctl_missing())?;
+        if !o.status.success() {
+            Err(systemctl_failed(&o))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Delete the service
+    pub fn delete(&mut self) -> Result<(), std::io::Error> {
+        let pb = self.systemd_path().join(format!("{}.service", self.name));
+        log::info!("Deleting {}", pb.display());
+        std::fs::remove_file(pb)
+    }
+
+    #[cfg(feature = "async")]
+    /// Delete the service
+    pub async fn delete_async(&mut self) -> Result<(), std::io::Error> {
+        let pb = self.systemd_path().join(format!("{}.service", self.name));
+        log::info!("Deleting {}", pb.display());
+        tokio::fs::remove_file(pb).await
+    }
+
+    /// Reload system services if required
+    fn reload(&mut self) -> Result<(), StartStopError> {
+        if !self.is_live_unit_dir() {
+            return Ok(());
+        }
+        let o = self
+            .systemctl()
+            .arg("daemon-reload")
+            .output()
+            .map_err(|_| systemctl_missing())?;
+        if !o.status.success() {
+            Err(systemctl_failed(&o))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Render the systemd unit file that create would write for the specified config, without touching the system
+    pub fn render_unit(&self, config: &ServiceConfig) -> String {
+        self.render_unit_file(config, false)
+    }
+
+    /// Render the systemd template unit that create_template would write for the specified config, without touching the system
+    pub fn render_template(&self, config: &ServiceConfig) -> String {
+        self.render_unit_file(config, true)
+    }
+
+    /// Render a unit file, a template keeps the %i and %I specifiers in the commands
+    fn render_unit_file(&self, config: &ServiceConfig, template: bool) -> String {
+        let mut con = String::new();
+        con.push_str("[Unit]\n");
+        con.push_str(&format!("Description={}\n", config.description));
+        if !config.documentation.is_empty() {
+            con.push_str(&format!(
+                "Documentation={}\n",
+                config.documentation.join(" ")
+            ));
+        }
+        if !config.dependencies.is_empty() {
+            let units = unit_names(&config.dependencies);
+            con.push_str(&format!("After={}\n", units.join(" ")));
+            con.push_str(&format!("Requires={}\n", units.join(" ")));
+        }
+        if !config.wants.is_empty() {
+            con.push_str(&format!("Wants={}\n", unit_names(&config.wants).join(" ")));
+        }
+        for condition in &config.conditions {
+            con.push_str(&format!("{}\n", condition.directive()));
+        }
+        con.push_str("[Service]\n");
+        match config.service_type {
+            ServiceType::Simple => {}
+            ServiceType::Notify => {
+                con.push_str("Type=notify\n");
+                con.push_str("NotifyAccess=main\n");
+            }
+            ServiceType::Forking => {
+                con.push_str("Type=forking\n");
+            }
+            ServiceType::Oneshot => con.push_str("Type=oneshot\n"),
+        }
+        if let Some(pid_file) = &config.pid_file {
+            con.push_str(&format!("PIDFile={}\n", pid_file.display()));
+        }
+        if let Some(user) = &config.username {
+            con.push_str(&format!("User={}\n", user));
+        }
+        if let Some(group) = &config.group {
+            con.push_str(&format!("Group={}\n", group));
+        }
+        if !config.runtime_directory.is_empty() {
+            let dirs = config.runtime_directory.join(" ");
+            con.push_str(&format!("RuntimeDirectory={}\n", dirs));
+        }
+        let state_directories = self.state_directories(config);
+        if !state_directories.is_empty() {
+            let dirs = state_directories.join(" ");
+            con.push_str(&format!("StateDirectory={}\n", dirs));
+        }
+        let config_path = self.resolved_config_path(config);
+        con.push_str(&format!("WorkingDirectory={}\n", config_path.display()));
+        for (key, value) in &config.environment {
+            con.push_str(&format!("Environment={}\n", escape_environment(key, value)));
+        }
+        let mut exec = escape_exec_arg(&config.binary.display().to_string(), template);
+        for arg in &config.arguments {
+            exec.push(' ');
+            exec.push_str(&escape_exec_arg(arg, template));
+        }
+        for pre in &config.exec_start_pre {
+            let pre = escape_exec_command(pre, template);
+            con.push_str(&format!("ExecStartPre={}\n", pre));
+        }
+        con.push_str(&format!("ExecStart={}\n", exec));
+        for post in &config.exec_stop_post {
+            let post = escape_exec_command(post, template);
+            con.push_str(&format!("ExecStopPost={}\n", post));
+        }
+        if let Some(reload) = &config.reload_command {
+            con.push_str(&format!("ExecReload={}\n", reload));
+        }
+        if let Some(timeout) = config.stop_timeout {
+            con.push_str(&format!("TimeoutStopSec={}ms\n", timeout.as_millis()));
+        }
+        if let Some(mode) = &config.kill_mode {
+            con.push_str(&format!("KillMode={}\n", mode.directive()));
+        }
+        if let Some(signal) = &config.kill_signal {
+            con.push_str(&format!("KillSignal={}\n", signal));
+        }
+        if let Some(output) = &config.standard_output {
+            con.push_str(&format!("StandardOutput={}\n", output.directive()));
+        }
+        if let Some(error) = &config.standard_error {
+            con.push_str(&format!("StandardError={}\n", error.directive()));
+        }
+        if let Some(watchdog) = config.watchdog {
+            con.push_str(&format!("WatchdogSec={}ms\n", watchdog.as_millis()));
+        }
+        if config.restart_policy != RestartPolicy::No {
+            con.push_str(&format!("Restart={}\n", config.restart_policy.directive()));
+        }
+        if let Some(sec) = config.restart_sec {
+            con.push_str(&format!("RestartSec={}ms\n", sec.as_millis()));
+        }
+        if let Some(limit) = config.limit_nofile {
+            con.push_str(&format!("LimitNOFILE={}\n", limit));
+        }
+        if let Some(memory) = &config.memory_max {
+            con.push_str(&format!("MemoryMax={}\n", memory));
+        }
+        if let Some(nice) = config.nice {
+            con.push_str(&format!("Nice={}\n", nice));
+        }
+        if let Some(adjust) = config.oom_score_adjust {
+            con.push_str(&format!("OOMScoreAdjust={}\n", adjust));
+        }
+        if let Some(quota) = &config.cpu_quota {
+            con.push_str(&format!("CPUQuota={}\n", quota));
+        }
+        if let Some(cpus) = &config.cpu_affinity {
+            con.push_str(&format!("CPUAffinity={}\n", cpus));
+        }
+        if let Some(cpus) = &config.allowed_cpus {
+            con.push_str(&format!("AllowedCPUs={}\n", cpus));
+        }
+        if let Some(slice) = &config.slice {
+            con.push_str(&format!("Slice={}\n", slice));
+        }
+        if let Some(protect) = &config.sandbox.protect_system {
+            con.push_str(&format!("ProtectSystem={}\n", protect.directive()));
+        }
+        if config.sandbox.protect_home {
+            con.push_str("ProtectHome=true\n");
+        }
+        if config.sandbox.private_tmp {
+            con.push_str("PrivateTmp=true\n");
+        }
+        if config.sandbox.no_new_privileges {
+            con.push_str("NoNewPrivileges=true\n");
+        }
+        if !config.sandbox.read_write_paths.is_empty() {
+            let paths: Vec<String> = config
+                .sandbox
+                .read_write_paths
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect();
+            con.push_str(&format!("ReadWritePaths={}\n", paths.join(" ")));
+        }
+        if let Some(target) = &config.install_target {
+            let target = match self.scope {
+                Scope::User if target == "multi-user.target" => "default.target",
+                _ => target,
+            };
+            con.push_str(&format!("\n[Install]\nWantedBy={}\n", target));
+            if !config.also.is_empty() {
+                con.push_str(&format!("Also={}\n", config.also.join(" ")));
+            }
+        }
+        con
+    }
+
+    /// Read back the configuration of the installed service by parsing its unit file
+    pub fn query_config(&self) -> Result<ServiceConfig, std::io::Error> {
+        let pb = self.systemd_path().join(format!("{}.service", self.name));
+        let contents = std::fs::read_to_string(pb)?;
+        let mut config = ServiceConfig::new(Vec::new(), String::new(), PathBuf::new(), None);
+        config.install_target = None;
+        for line in contents.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "Description" => config.description = value.to_string(),
+                "After" => {
+                    config.dependencies = value
+                        .split_whitespace()
+                        .map(|d| d.strip_suffix(".service").unwrap_or(d).to_string())
+                        .collect()
+                }
+                "Wants" => {
+                    config.wants = value
+                        .split_whitespace()
+                        .map(|d| d.strip_suffix(".service").unwrap_or(d).to_string())
+                        .collect()
+                }
+                "Documentation" => {
+                    config.documentation = value.split_whitespace().map(String::from).collect()
+                }
+                "Type" => {
+                    config.service_type = match value {
+                        "notify" => ServiceType::Notify,
+                        "forking" => ServiceType::Forking,
+                        "oneshot" => ServiceType::Oneshot,
+                        _ => ServiceType::Simple,
+                    }
+                }
+                "PIDFile" => config.pid_file = Some(PathBuf::from(value)),
+                "User" => config.username = Some(value.to_string()),
+                "Group" => config.group = Some(value.to_string()),
+                "RuntimeDirectory" => {
+                    config.runtime_directory = value.split_whitespace().map(String::from).collect()
+                }
+                "StateDirectory" => {
+                    config.state_directory = value.split_whitespace().map(String::from).collect()
+                }
+                "WorkingDirectory" => config.config_path = PathBuf::from(value),
+                "Environment" => {
+                    for assignment in split_unit_words(value) {
+                        if let Some((k, v)) = assignment.split_once('=') {
+                            config.environment.push((k.to_string(), v.to_string()));
+                        }
+                    }
+                }
+                "ExecStart" => {
+                    let mut words = split_unit_words(value).into_iter();
+                    config.binary = words.next().map(PathBuf::from).unwrap_or_default();
+                    config.arguments = words.collect();
+                }
+                "ExecStartPre" => config.exec_start_pre.push(split_unit_words(value)),
+                "ExecStopPost" => config.exec_stop_post.push(split_unit_words(value)),
+                "ExecReload" => config.reload_command = Some(value.to_string()),
+                "TimeoutStopSec" => config.stop_timeout = parse_unit_duration(value),
+                "KillMode" => config.kill_mode = KillMode::from_directive(value),
+                "KillSignal" => config.kill_signal = Some(value.to_string()),
+                "StandardOutput" => config.standard_output = OutputTarget::from_directive(value),
+                "StandardError" => config.standard_error = OutputTarget::from_directive(value),
+                "WatchdogSec" => config.watchdog = parse_unit_duration(value),
+                "Restart" => config.restart_policy = RestartPolicy::from_directive(value),
+                "RestartSec" => config.restart_sec = parse_unit_duration(value),
+                "LimitNOFILE" => config.limit_nofile = value.parse().ok(),
+                "MemoryMax" => config.memory_max = Some(value.to_string()),
+                "Nice" => config.nice = value.parse().ok(),
+                "OOMScoreAdjust" => config.oom_score_adjust = value.parse().ok(),
+                "CPUQuota" => config.cpu_quota = Some(value.to_string()),
+                "CPUAffinity" => config.cpu_affinity = Some(value.to_string()),
+                "AllowedCPUs" => config.allowed_cpus = Some(value.to_string()),
+                "Slice" => config.slice = Some(value.to_string()),
+                "ProtectSystem" => {
+                    config.sandbox.protect_system = ProtectSystem::from_directive(value)
+                }
+                "ProtectHome" => config.sandbox.protect_home = parse_unit_bool(value),
+                "PrivateTmp" => config.sandbox.private_tmp = parse_unit_bool(value),
+                "NoNewPrivileges" => config.sandbox.no_new_privileges = parse_unit_bool(value),
+                "ReadWritePaths" => {
+                    config.sandbox.read_write_paths =
+                        value.split_whitespace().map(PathBuf::from).collect()
+                }
+                "WantedBy" => config.install_target = Some(value.to_string()),
+                "Also" => config.also = value.split_whitespace().map(String::from).collect(),
+                key => {
+                    if let Some(condition) = Condition::from_directive(key, value) {
+                        config.conditions.push(condition);
+                    }
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Create the service
+    pub fn create(&mut self, config: ServiceConfig) -> Result<(), CreateError> {
+        self.write_unit(config, false)
+    }
+
+    /// Create a template unit named after the service followed by `@.service`, instances of it are started with start_instance.
+    /// The %i and %I specifiers in the binary, arguments, and commands of the config are replaced by systemd with the instance name
+    pub fn create_template(&mut self, config: ServiceConfig) -> Result<(), CreateError> {
+        self.write_unit(config, true)
+    }
+
+    /// Write the unit file or template unit file for the config and reload systemd.
+    /// A template binary containing specifiers is not checked for existence, since systemd only fills them in when an instance starts
+    fn write_unit(&mut self, mut config: ServiceConfig, template: bool) -> Result<(), CreateError> {
+        use std::io::Write;
+        let specifiers = template && config.binary.to_string_lossy().contains('%');
+        config.binary = config.absolute_binary(config.allow_missing_binary || specifiers)?;
+        self.validate_config(&config)?;
+        let con = self.render_unit_file(&config, template);
+        if self.is_live_unit_dir() && !self.default_state_directory(&config) {
+            std::fs::create_dir_all(self.resolved_config_path(&config))
+                .map_err(CreateError::from)?;
+        }
+        if self.scope == Scope::User || self.unit_dir.is_some() {
+            std::fs::create_dir_all(self.systemd_path()).map_err(CreateError::from)?;
+        }
+        let file_name = if template {
+            format!("{}@.service", self.name)
+        } else {
+            format!("{}.service", self.name)
+        };
+        let pb = self.systemd_path().join(file_name);
+        log::info!("Saving service file as {}", pb.display());
+        let mut fpw = std::fs::File::create(pb).map_err(CreateError::from)?;
+        fpw.write_all(con.as_bytes()).map_err(CreateError::from)?;
+        Ok(self.reload()?)
     }
 
     #[cfg(feature = "async")]
     /// Create the service
-    pub async fn create_async(&mut self, config: ServiceConfig) -> Result<(), CreateError> {
+    pub async fn create_async(&mut self, mut config: ServiceConfig) -> Result<(), CreateError> {
         use tokio::io::AsyncWriteExt;
 
-        let con = self.build_systemd_file(config);
+        config.binary = config.absolute_binary(config.allow_missing_binary)?;
+        self.validate_config(&config)?;
+        let con = self.render_unit(&config);
+        if self.is_live_unit_dir() && !self.default_state_directory(&config) {
+            tokio::fs::create_dir_all(self.resolved_config_path(&config))
+                .await
+                .map_err(CreateError::from)?;
+        }
+        if self.scope == Scope::User || self.unit_dir.is_some() {
+            tokio::fs::create_dir_all(self.systemd_path())
+                .await
+                .map_err(CreateError::from)?;
+        }
         let pb = self.systemd_path().join(format!("{}.service", self.name));
-        println!("Saving service file as {}", pb.display());
+        log::info!("Saving service file as {}", pb.display());
         let mut fpw = tokio::fs::File::create(pb)
             .await
-            .map_err(CreateError::FileIoError)?;
+            .map_err(CreateError::from)?;
         fpw.write_all(con.as_bytes())
             .await
-            .map_err(CreateError::FileIoError)?;
+            .map_err(CreateError::from)?;
+        Ok(self.reload()?)
+    }
+
+    /// Render the systemd timer unit that create_timer would write for the schedule, without touching the system
+    pub fn render_timer(&self, schedule: &TimerSchedule) -> String {
+        let mut con = String::new();
+        con.push_str("[Unit]\n");
+        con.push_str(&format!("Description=Timer for {}\n", self.name));
+        con.push_str("[Timer]\n");
+        match schedule {
+            TimerSchedule::Calendar(calendar) => {
+                con.push_str(&format!("OnCalendar={}\n", calendar));
+            }
+            TimerSchedule::Interval(interval) => {
+                con.push_str(&format!("OnBootSec={}ms\n", interval.as_millis()));
+                con.push_str(&format!("OnUnitActiveSec={}ms\n", interval.as_millis()));
+            }
+        }
+        con.push_str(&format!("Unit={}.service\n", self.name));
+        con.push_str("\n[Install]\nWantedBy=timers.target\n");
+        con
+    }
+
+    /// Write a timer unit that runs the service on the schedule, then enable and start the timer.
+    /// The service must already be created with [ServiceType::Oneshot], otherwise [CreateError::InvalidConfig] is returned.
+    /// It should have no install target, so that only the timer starts it.
+    pub fn create_timer(&mut self, schedule: TimerSchedule) -> Result<(), CreateError> {
+        use std::io::Write;
+        if let TimerSchedule::Calendar(calendar) = &schedule {
+            if calendar.trim().is_empty() {
+                return Err(CreateError::InvalidConfig(
+                    "the calendar event of the timer is empty".to_string(),
+                ));
+            }
+        }
+        let config = self.query_config().map_err(CreateError::from)?;
+        if !matches!(config.service_type, ServiceType::Oneshot) {
+            return Err(CreateError::InvalidConfig(format!(
+                "the service {} started by a timer must be Type=oneshot",
+                self.name
+            )));
+        }
+        let con = self.render_timer(&schedule);
+        std::fs::create_dir_all(self.systemd_path()).map_err(CreateError::from)?;
+        let pb = self.systemd_path().join(format!("{}.timer", self.name));
+        log::info!("Saving timer file as {}", pb.display());
+        let mut fpw = std::fs::File::create(pb).map_err(CreateError::from)?;
+        fpw.write_all(con.as_bytes()).map_err(CreateError::from)?;
+        self.reload()?;
+        let o = self
+            .systemctl()
+            .arg("enable")
+            .arg("--now")
+            .arg(format!("{}.timer", self.name))
+            .output()
+            .map_err(|_| CreateError::from(systemctl_missing()))?;
+        if !o.status.success() {
+            Err(CreateError::from(systemctl_failed(&o)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Stop and disable the timer of the service, then delete its timer unit
+    pub fn delete_timer(&mut self) -> Result<(), CreateError> {
+        let o = self
+            .systemctl()
+            .arg("disable")
+            .arg("--now")
+            .arg(format!("{}.timer", self.name))
+            .output()
+            .map_err(|_| CreateError::from(systemctl_missing()))?;
+        if !o.status.success() {
+            return Err(CreateError::from(systemctl_failed(&o)));
+        }
+        let pb = self.systemd_path().join(format!("{}.timer", self.name));
+        log::info!("Deleting {}", pb.display());
+        std::fs::remove_file(pb).map_err(CreateError::from)?;
+        Ok(self.reload()?)
+    }
+
+    /// Get the contents of the socket unit that activates the service
+    pub fn render_socket(&self, listen: &SocketSpec) -> Result<String, CreateError> {
+        let mut con = String::new();
+        con.push_str("[Unit]\n");
+        con.push_str(&format!("Description=Socket for {}\n", self.name));
+        con.push_str("[Socket]\n");
+        match listen {
+            SocketSpec::Stream(addr) => {
+                con.push_str(&format!("ListenStream={}\n", addr.directive_value()?));
+            }
+            SocketSpec::Datagram(addr) => {
+                con.push_str(&format!("ListenDatagram={}\n", addr.directive_value()?));
+            }
+        }
+        con.push_str(&format!("Service={}.service\n", self.name));
+        con.push_str("\n[Install]\nWantedBy=sockets.target\n");
+        Ok(con)
+    }
+
+    /// Write a socket unit that starts the service when a connection or datagram arrives, then enable and start the socket.
+    /// The service must already be created without an install target, see [ServiceConfig::socket_activated], otherwise [CreateError::InvalidConfig] is returned.
+    /// The service receives the listening socket as file descriptor 3.
+    pub fn create_socket(&mut self, listen: SocketSpec) -> Result<(), CreateError> {
+        use std::io::Write;
+        let con = self.render_socket(&listen)?;
+        let config = self.query_config().map_err(CreateError::from)?;
+        if config.install_target.is_some() {
+            return Err(CreateError::InvalidConfig(format!(
+                "the service {} has an install target, so it does not wait for its socket",
+                self.name
+            )));
+        }
+        std::fs::create_dir_all(self.systemd_path()).map_err(CreateError::from)?;
+        let pb = self.systemd_path().join(format!("{}.socket", self.name));
+        log::info!("Saving socket file as {}", pb.display());
+        let mut fpw = std::fs::File::create(pb).map_err(CreateError::from)?;
+        fpw.write_all(con.as_bytes()).map_err(CreateError::from)?;
+        self.reload()?;
+        let o = self
+            .systemctl()
+            .arg("enable")
+            .arg("--now")
+            .arg(format!("{}.socket", self.name))
+            .output()
+            .map_err(|_| CreateError::from(systemctl_missing()))?;
+        if !o.status.success() {
+            Err(CreateError::from(systemctl_failed(&o)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Stop and disable the socket of the service, then delete its socket unit
+    pub fn delete_socket(&mut self) -> Result<(), CreateError> {
+        let o = self
+            .systemctl()
+            .arg("disable")
+            .arg("--now")
+            .arg(format!("{}.socket", self.name))
+            .output()
+            .map_err(|_| CreateError::from(systemctl_missing()))?;
+        if !o.status.success() {
+            return Err(CreateError::from(systemctl_failed(&o)));
+        }
+        let pb = self.systemd_path().join(format!("{}.socket", self.name));
+        log::info!("Deleting {}", pb.display());
+        std::fs::remove_file(pb).map_err(CreateError::from)?;
         Ok(self.reload()?)
     }
 
+    /// Rewrite the unit file of an existing service and reload systemd, the service does not need to be stopped.
+    /// A running service keeps its old settings until it is restarted.
+    pub fn update(&mut self, config: ServiceConfig) -> Result<(), CreateError> {
+        self.create(config)
+    }
+
+    /// Change only the description of the service in its unit file and reload systemd, leaving the rest of the unit as it is
+    pub fn set_description(&mut self, description: &str) -> Result<(), CreateError> {
+        if description.contains('\n') {
+            return Err(CreateError::InvalidConfig(
+                "the description contains a newline".to_string(),
+            ));
+        }
+        let pb = self.systemd_path().join(format!("{}.service", self.name));
+        let contents = std::fs::read_to_string(&pb).map_err(CreateError::from)?;
+        let mut con = String::new();
+        for line in contents.lines() {
+            if line.starts_with("Description=") {
+                con.push_str(&format!("Description={}\n", description));
+            } else {
+                con.push_str(line);
+                con.push('\n');
+            }
+        }
+        std::fs::write(&pb, con).map_err(CreateError::from)?;
+        Ok(self.reload()?)
+    }
+
+    /// Create the service when it does not exist, otherwise update it to the config
+    pub fn ensure(&mut self, config: ServiceConfig) -> Result<crate::EnsureOutcome, CreateError> {
+        if self.exists() {
+            self.update(config)?;
+            Ok(crate::EnsureOutcome::Updated)
+        } else {
+            self.create(config)?;
+            Ok(crate::EnsureOutcome::Created)
+        }
+    }
+
+    /// Run the service function in the foreground, ctrl-c stops the service
+    pub fn run_standalone<T: Send + 'static>(&self, service_main: ServiceFn<T>) -> u32 {
+        run_with_signals(service_main, true)
+    }
+
     /// Run the required dispatch code
     pub fn dispatch(&self, service_main: DispatchFn) -> Result<(), u32> {
         service_main();
         Ok(())
     }
+
+    /// Tell systemd that the service has finished starting, for services created with [ServiceType::Notify]
+    #[cfg(feature = "notify")]
+    pub fn notify_ready() -> std::io::Result<()> {
+        sd_notify("READY=1")
+    }
+
+    /// Ping the systemd watchdog, this should be done at least every half of [Service::watchdog_interval]
+    #[cfg(feature = "notify")]
+    pub fn notify_watchdog() -> std::io::Result<()> {
+        sd_notify("WATCHDOG=1")
+    }
+
+    /// The watchdog interval systemd expects for this process, none when the watchdog is not enabled
+    #[cfg(feature = "notify")]
+    pub fn watchdog_interval() -> Option<std::time::Duration> {
+        let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+        if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+            if pid.parse::<u32>().ok()? != std::process::id() {
+                return None;
+            }
+        }
+        Some(std::time::Duration::from_micros(usec))
+    }
+}
+
+/// Send a state string such as `READY=1` to systemd, this does nothing when not run by systemd
+#[cfg(feature = "notify")]
+fn sd_notify(state: &str) -> std::io::Result<()> {
+    let path = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    let socket = std::os::unix::net::UnixDatagram::unbound()?;
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str());
+    if let Some(name) = bytes.strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        socket.send_to_addr(state.as_bytes(), &addr)?;
+    } else {
+        socket.send_to(state.as_bytes(), &path)?;
+    }
+    Ok(())
+}
+
+/// Runs the main service function, delivering SIGTERM and SIGINT to it as [crate::ServiceEvent::Stop] and SIGHUP as [crate::ServiceEvent::Reload].
+/// For a standalone run without any signal handling, the service function can be called directly with None for both channels.
+pub fn run_service<T: Send + 'static>(service_main: ServiceFn<T>) {
+    let code = run_with_signals(service_main, false);
+    if code != 0 {
+        std::process::exit(code as i32);
+    }
+}
+
+/// Run the service function with the signals delivered as service events, returning its exit code
+fn run_with_signals<T: Send + 'static>(service_main: ServiceFn<T>, standalone_mode: bool) -> u32 {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let tx2: std::sync::mpsc::Sender<crate::ServiceEvent<T>> = tx.clone();
+    let signals = signal_hook::iterator::Signals::new([
+        signal_hook::consts::SIGTERM,
+        signal_hook::consts::SIGINT,
+        signal_hook::consts::SIGHUP,
+    ]);
+    let (signal_handle, signal_thread) = match signals {
+        Ok(mut signals) => {
+            let handle = signals.handle();
+            let thread = std::thread::spawn(move || {
+                for signal in signals.forever() {
+                    let event = if signal == signal_hook::consts::SIGHUP {
+                        crate::ServiceEvent::Reload
+                    } else {
+                        crate::ServiceEvent::Stop
+                    };
+                    let _ = tx.send(event);
+                }
+            });
+            (Some(handle), Some(thread))
+        }
+        Err(e) => {
+            log::error!("Unable to register signal handlers: {:?}", e);
+            (None, None)
+        }
+    };
+    let args = std::env::args().collect();
+    let code = service_main(Some(rx), Some(tx2), args, standalone_mode).report();
+    if let Some(handle) = signal_handle {
+        handle.close();
+    }
+    if let Some(thread) = signal_thread {
+        let _ = thread.join();
+    }
+    code
+}
+
+#[cfg(feature = "async")]
+/// Runs the async service function, delivering SIGTERM and SIGINT to it as [crate::ServiceEvent::Stop] and SIGHUP as [crate::ServiceEvent::Reload].
+/// The service function should return once it receives [crate::ServiceEvent::Stop], a non zero exit code becomes the exit code of the process.
+pub async fn run_service_async<T, F, Fut>(service_main: F)
+where
+    T: Send + 'static,
+    F: FnOnce(
+        tokio::sync::mpsc::Receiver<crate::ServiceEvent<T>>,
+        tokio::sync::mpsc::Sender<crate::ServiceEvent<T>>,
+        Vec<String>,
+        bool,
+    ) -> Fut,
+    Fut: std::future::Future<Output = crate::ServiceExit>,
+{
+    use tokio::signal::unix::{signal, SignalKind};
+    let (tx, rx) = tokio::sync::mpsc::channel(10);
+    let tx2: tokio::sync::mpsc::Sender<crate::ServiceEvent<T>> = tx.clone();
+    let signals = (|| {
+        Ok::<_, std::io::Error>((
+            signal(SignalKind::terminate())?,
+            signal(SignalKind::interrupt())?,
+            signal(SignalKind::hangup())?,
+        ))
+    })();
+    let signal_task = match signals {
+        Ok((mut term, mut int, mut hup)) => Some(tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    _ = term.recv() => crate::ServiceEvent::Stop,
+                    _ = int.recv() => crate::ServiceEvent::Stop,
+                    _ = hup.recv() => crate::ServiceEvent::Reload,
+                };
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        })),
+        Err(e) => {
+            log::error!("Unable to register signal handlers: {:?}", e);
+            None
+        }
+    };
+    let args = std::env::args().collect();
+    let code = service_main(rx, tx2, args, false).await.report();
+    if let Some(task) = signal_task {
+        task.abort();
+    }
+    if code != 0 {
+        std::process::exit(code as i32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Arguments that need quoting or escaping in a unit file
+    const ARGS: [&str; 14] = [
+        "plain",
+        "with space",
+        "quote\"inside",
+        "back\\slash",
+        "trailing\\",
+        "100%",
+        "$HOME",
+        "C:\\Program Files\\app\\bin.exe",
+        "",
+        "tab\there",
+        "new\nline",
+        "it's",
+        "a;b",
+        ";",
+    ];
+
+    /// Split a command line the way systemd splits ExecStart=, removing quotes, undoing backslash escapes, and replacing %% and $$
+    fn systemd_split(line: &str) -> Vec<String> {
+        let mut words = Vec::new();
+        let mut word = String::new();
+        let mut in_word = false;
+        let mut quote = None;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    in_word = true;
+                    match chars.next() {
+                        Some('n') => word.push('\n'),
+                        Some('t') => word.push('\t'),
+                        Some(c) => word.push(c),
+                        None => {}
+                    }
+                }
+                '"' | '\'' if quote == Some(c) => quote = None,
+                '"' | '\'' if quote.is_none() => {
+                    in_word = true;
+                    quote = Some(c);
+                }
+                '%' | '$' => {
+                    in_word = true;
+                    assert_eq!(chars.next(), Some(c), "unescaped {} in {}", c, line);
+                    word.push(c);
+                }
+                c if c.is_whitespace() && quote.is_none() => {
+                    if in_word {
+                        words.push(std::mem::take(&mut word));
+                        in_word = false;
+                    }
+                }
+                c => {
+                    in_word = true;
+                    word.push(c);
+                }
+            }
+        }
+        assert!(quote.is_none(), "unterminated quote in {}", line);
+        if in_word {
+            words.push(word);
+        }
+        words
+    }
+
+    #[test]
+    fn exec_args_round_trip() {
+        let line: Vec<String> = ARGS.iter().map(|a| escape_exec_arg(a, false)).collect();
+        let line = line.join(" ");
+        assert_eq!(systemd_split(&line), ARGS);
+        assert_eq!(split_unit_words(&line), ARGS);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn config_from_json_renders_unit() {
+        let json = r#"{
+            "binary": "/usr/bin/example",
+            "arguments": ["--port", "80 80", "50%"],
+            "description": "Example service",
+            "nice": 5
+        }"#;
+        let config: ServiceConfig = serde_json::from_str(json).unwrap();
+        config.validate().unwrap();
+        let unit = Service::new("example".into()).render_unit(&config);
+        assert!(unit.contains("Description=Example service\n"));
+        assert!(unit.contains("Nice=5\n"));
+        assert!(unit.contains("WantedBy=multi-user.target\n"));
+        let exec = unit
+            .lines()
+            .find_map(|l| l.strip_prefix("ExecStart="))
+            .unwrap();
+        assert_eq!(
+            systemd_split(exec),
+            ["/usr/bin/example", "--port", "80 80", "50%"]
+        );
+    }
+
+    #[test]
+    fn exec_args_keep_instance_specifiers_in_templates() {
+        assert_eq!(escape_exec_arg("--name=%i", true), "--name=%i");
+        assert_eq!(escape_exec_arg("--name=%i", false), "--name=%%i");
+        assert_eq!(escape_exec_arg("100%", true), "100%%");
+    }
+
+    #[test]
+    fn timer_needs_oneshot_service() {
+        let dir = std::env::temp_dir().join(format!("service-timer-{}", std::process::id()));
+        let mut service = Service::new("example".into());
+        service.set_unit_dir(Some(dir.clone()));
+        let mut config = ServiceConfig::new(
+            Vec::new(),
+            "Example".to_string(),
+            PathBuf::from("/usr/bin/example"),
+            None,
+        );
+        config.allow_missing_binary = true;
+        config.install_target = None;
+        service.create(config).unwrap();
+        let schedule = TimerSchedule::Interval(std::time::Duration::from_secs(60));
+        let result = service.create_timer(schedule);
+        assert!(matches!(result, Err(CreateError::InvalidConfig(_))));
+        assert!(!dir.join("example.timer").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn socket_activated_service_has_no_install_target() {
+        let mut config = ServiceConfig::new(
+            Vec::new(),
+            "Example".to_string(),
+            PathBuf::from("/usr/bin/example"),
+            None,
+        );
+        config.allow_missing_binary = true;
+        config.socket_activated = true;
+        assert!(matches!(
+            config.validate(),
+            Err(CreateError::InvalidConfig(_))
+        ));
+
+        let dir = std::env::temp_dir().join(format!("service-socket-{}", std::process::id()));
+        let mut service = Service::new("example".into());
+        service.set_unit_dir(Some(dir.clone()));
+        config.socket_activated = false;
+        service.create(config).unwrap();
+        let listen = SocketSpec::Stream(ListenAddress::Path(PathBuf::from("/run/example.sock")));
+        let result = service.create_socket(listen);
+        assert!(matches!(result, Err(CreateError::InvalidConfig(_))));
+        assert!(!dir.join("example.socket").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn list_uses_systemctl_path() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("service-list-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let systemctl = dir.join("systemctl");
+        std::fs::write(
+            &systemctl,
+            "#!/bin/sh\necho 'example.service enabled'\necho 'other.service disabled'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&systemctl, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut service = Service::new("example".into());
+        service.set_systemctl_path(Some(systemctl));
+        assert_eq!(service.list(None).unwrap(), ["example", "other"]);
+        assert_eq!(service.list(Some("ex")).unwrap(), ["example"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn exec_commands_keep_arguments_with_spaces() {
+        let mut config = ServiceConfig::new(
+            Vec::new(),
+            String::new(),
+            PathBuf::from("/usr/bin/example"),
+            None,
+        );
+        let pre = vec![
+            "/bin/sh".to_string(),
+            "-c".to_string(),
+            "echo hi".to_string(),
+        ];
+        let post = vec!["/bin/rm".to_string(), "/tmp/a file".to_string()];
+        config.exec_start_pre = vec![pre.clone()];
+        config.exec_stop_post = vec![post.clone()];
+        let unit = Service::new("example".into()).render_unit(&config);
+        let value = |key: &str| {
+            unit.lines()
+                .find_map(|l| l.strip_prefix(key))
+                .map(systemd_split)
+                .unwrap()
+        };
+        assert_eq!(value("ExecStartPre="), pre);
+        assert_eq!(value("ExecStopPost="), post);
+    }
+
+    #[test]
+    fn default_config_path_is_a_state_directory() {
+        let service = Service::new("example".into());
+        let mut config = ServiceConfig::new(
+            Vec::new(),
+            String::new(),
+            PathBuf::from("/usr/bin/example"),
+            Some("nobody".to_string()),
+        );
+        let unit = service.render_unit(&config);
+        assert!(unit.contains("StateDirectory=example\n"));
+        assert!(unit.contains("WorkingDirectory=/var/lib/example\n"));
+
+        config.state_directory = vec!["example".to_string(), "other".to_string()];
+        let unit = service.render_unit(&config);
+        assert!(unit.contains("StateDirectory=example other\n"));
+
+        config.state_directory.clear();
+        config.config_path = PathBuf::from("/srv/example");
+        let unit = service.render_unit(&config);
+        assert!(!unit.contains("StateDirectory="));
+        assert!(unit.contains("WorkingDirectory=/srv/example\n"));
+    }
+
+    /// A config for /usr/bin/example with nothing else set
+    fn example_config() -> ServiceConfig {
+        ServiceConfig::new(
+            Vec::new(),
+            "Example".to_string(),
+            PathBuf::from("/usr/bin/example"),
+            None,
+        )
+    }
+
+    /// Check that the config is rejected by validate
+    fn assert_invalid(config: &ServiceConfig) {
+        assert!(matches!(
+            config.validate(),
+            Err(CreateError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn restart_and_stop_directives_render() {
+        let mut config = example_config();
+        let unit = Service::new("example".into()).render_unit(&config);
+        assert!(!unit.contains("Restart="));
+        assert!(!unit.contains("KillMode="));
+
+        config.restart_policy = RestartPolicy::OnFailure;
+        config.restart_sec = Some(std::time::Duration::from_millis(1500));
+        config.stop_timeout = Some(std::time::Duration::from_secs(10));
+        config.watchdog = Some(std::time::Duration::from_secs(30));
+        config.kill_mode = Some(KillMode::Mixed);
+        config.kill_signal = Some("SIGINT".to_string());
+        let unit = Service::new("example".into()).render_unit(&config);
+        assert!(unit.contains("Restart=on-failure\n"));
+        assert!(unit.contains("RestartSec=1500ms\n"));
+        assert!(unit.contains("TimeoutStopSec=10000ms\n"));
+        assert!(unit.contains("WatchdogSec=30000ms\n"));
+        assert!(unit.contains("KillMode=mixed\n"));
+        assert!(unit.contains("KillSignal=SIGINT\n"));
+    }
+
+    #[test]
+    fn resource_directives_render() {
+        let mut config = example_config();
+        config.limit_nofile = Some(65536);
+        config.memory_max = Some("512M".to_string());
+        config.nice = Some(-20);
+        config.oom_score_adjust = Some(1000);
+        config.cpu_quota = Some("20%".to_string());
+        config.cpu_affinity = Some("0-3,5".to_string());
+        config.allowed_cpus = Some("2".to_string());
+        config.slice = Some("batch.slice".to_string());
+        config.validate().unwrap();
+        let unit = Service::new("example".into()).render_unit(&config);
+        assert!(unit.contains("LimitNOFILE=65536\n"));
+        assert!(unit.contains("MemoryMax=512M\n"));
+        assert!(unit.contains("Nice=-20\n"));
+        assert!(unit.contains("OOMScoreAdjust=1000\n"));
+        assert!(unit.contains("CPUQuota=20%\n"));
+        assert!(unit.contains("CPUAffinity=0-3,5\n"));
+        assert!(unit.contains("AllowedCPUs=2\n"));
+        assert!(unit.contains("Slice=batch.slice\n"));
+    }
+
+    #[test]
+    fn resource_values_are_validated() {
+        for memory in ["512", "1.5G", "50%", "infinity"] {
+            let mut config = example_config();
+            config.memory_max = Some(memory.to_string());
+            config.validate().unwrap();
+        }
+        for memory in ["", "M", "512MB", "-1", "1.G", "lots"] {
+            let mut config = example_config();
+            config.memory_max = Some(memory.to_string());
+            assert_invalid(&config);
+        }
+        for nice in [-21, 20] {
+            let mut config = example_config();
+            config.nice = Some(nice);
+            assert_invalid(&config);
+        }
+        for adjust in [-1001, 1001] {
+            let mut config = example_config();
+            config.oom_score_adjust = Some(adjust);
+            assert_invalid(&config);
+        }
+        for quota in ["20", "0%", "-5%", "%", "2.5%"] {
+            let mut config = example_config();
+            config.cpu_quota = Some(quota.to_string());
+            assert_invalid(&config);
+        }
+        for cpus in ["", "3-1", "0,", "a", "0 1"] {
+            let mut config = example_config();
+            config.cpu_affinity = Some(cpus.to_string());
+            assert_invalid(&config);
+        }
+        for slice in ["batch", "batch.service", ".slice"] {
+            let mut config = example_config();
+            config.slice = Some(slice.to_string());
+            assert_invalid(&config);
+        }
+    }
+
+    #[test]
+    fn output_and_condition_directives_render() {
+        let mut config = example_config();
+        config.standard_output = Some(OutputTarget::Append(PathBuf::from("/var/log/example.log")));
+        config.standard_error = Some(OutputTarget::Null);
+        config.conditions = vec![
+            Condition::new(ConditionCheck::PathExists(PathBuf::from(
+                "/etc/example.conf",
+            ))),
+            Condition {
+                check: ConditionCheck::PathIsDirectory(PathBuf::from("/mnt/data")),
+                negate: true,
+                assert: true,
+            },
+        ];
+        config.documentation = vec!["man:example(8)".to_string()];
+        config.wants = vec!["network-online".to_string()];
+        config.validate().unwrap();
+        let unit = Service::new("example".into()).render_unit(&config);
+        assert!(unit.contains("StandardOutput=append:/var/log/example.log\n"));
+        assert!(unit.contains("StandardError=null\n"));
+        assert!(unit.contains("ConditionPathExists=/etc/example.conf\n"));
+        assert!(unit.contains("AssertPathIsDirectory=!/mnt/data\n"));
+        assert!(unit.contains("Documentation=man:example(8)\n"));
+        assert!(unit.contains("Wants=network-online.service\n"));
+
+        let mut relative = example_config();
+        relative.standard_output = Some(OutputTarget::File(PathBuf::from("example.log")));
+        assert_invalid(&relative);
+        let mut relative = example_config();
+        relative.conditions = vec![Condition::new(ConditionCheck::FileNotEmpty(PathBuf::from(
+            "example.conf",
+        )))];
+        assert_invalid(&relative);
+        let mut undocumented = example_config();
+        undocumented.documentation = vec!["example docs".to_string()];
+        assert_invalid(&undocumented);
+    }
+
+    #[test]
+    fn directory_and_sandbox_directives_render() {
+        let mut config = example_config();
+        config.config_path = PathBuf::from("/srv/example");
+        let unit = Service::new("example".into()).render_unit(&config);
+        for directive in [
+            "ProtectSystem=",
+            "ProtectHome=",
+            "PrivateTmp=",
+            "NoNewPrivileges=",
+        ] {
+            assert!(!unit.contains(directive));
+        }
+
+        config.runtime_directory = vec!["example".to_string(), "example/sockets".to_string()];
+        config.sandbox = SandboxOptions::hardened();
+        config.sandbox.read_write_paths = vec![PathBuf::from("/srv/example")];
+        config.validate().unwrap();
+        let unit = Service::new("example".into()).render_unit(&config);
+        assert!(unit.contains("RuntimeDirectory=example example/sockets\n"));
+        assert!(unit.contains("ProtectSystem=strict\n"));
+        assert!(unit.contains("ProtectHome=true\n"));
+        assert!(unit.contains("PrivateTmp=true\n"));
+        assert!(unit.contains("NoNewPrivileges=true\n"));
+        assert!(unit.contains("ReadWritePaths=/srv/example\n"));
+
+        for dir in ["", "/run/example", "../example", "example/../other"] {
+            let mut invalid = example_config();
+            invalid.runtime_directory = vec![dir.to_string()];
+            assert_invalid(&invalid);
+        }
+        for path in ["srv/example", "/srv/my example"] {
+            let mut invalid = example_config();
+            invalid.sandbox.read_write_paths = vec![PathBuf::from(path)];
+            assert_invalid(&invalid);
+        }
+    }
+
+    #[test]
+    fn absolute_binary_is_canonical() {
+        let dir = std::env::temp_dir().join(format!("service-binary-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        std::fs::write(dir.join("bin").join("example"), "").unwrap();
+        std::os::unix::fs::symlink(dir.join("bin").join("example"), dir.join("link")).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        let config = |binary: PathBuf| ServiceConfig::new(Vec::new(), String::new(), binary, None);
+
+        let dotted = config(dir.join("bin").join("..").join("link"));
+        assert_eq!(
+            dotted.absolute_binary(false).unwrap(),
+            dir.join("bin").join("example")
+        );
+        let missing = config(dir.join("bin").join("..").join("missing"));
+        assert!(matches!(
+            missing.absolute_binary(false),
+            Err(CreateError::BinaryNotFound(_))
+        ));
+        assert_eq!(
+            missing.absolute_binary(true).unwrap(),
+            dir.join("bin").join("..").join("missing")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn xdg_dir_ignores_relative_values() {
+        let dir = |value: Option<&str>, home: Option<&str>| {
+            xdg_dir(value.map(Into::into), home.map(Into::into), ".local/state")
+        };
+        assert_eq!(
+            dir(Some("/state"), Some("/home/a")),
+            PathBuf::from("/state")
+        );
+        let fallback = PathBuf::from("/home/a/.local/state");
+        assert_eq!(dir(Some("state"), Some("/home/a")), fallback);
+        assert_eq!(dir(Some(""), Some("/home/a")), fallback);
+        assert_eq!(dir(None, Some("/home/a")), fallback);
+        assert!(dir(Some("state"), None).is_relative());
+    }
 }
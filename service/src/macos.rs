@@ -0,0 +1,935 @@
+//! MacOS specific code for managing a service
+
+use std::path::PathBuf;
+
+use crate::ServiceFn;
+
+/// Dummy function for uniformity to windows
+pub type DispatchFn = fn();
+
+#[derive(Debug)]
+/// Errors that can occur when interfacing with launchctl
+pub enum StartStopError {
+    /// Launchctl does not exist or is not callable for some reason
+    NoLaunchCtl,
+    /// The launchctl command returned an error
+    LaunchCtlFailed,
+    /// The service did not stop within the stop timeout
+    StopTimeout,
+}
+
+/// The macro generates the service function required
+#[macro_export]
+macro_rules! ServiceMacro {
+    ($entry:ident, $function:ident, $t:ident) => {
+        fn $entry() {
+            service::run_service::<$t>($function);
+        }
+    };
+    ($entry:ident, $function:ident, $t:ident, $config:expr) => {
+        fn $entry() {
+            let _config: service::RunConfig<$t> = $config;
+            service::run_service::<$t>($function);
+        }
+    };
+}
+
+/// This macro is for the async dispatch on a macos service
+#[macro_export]
+macro_rules! DispatchAsync {
+    ($self:ident, $function:ident) => {{
+        $function().await;
+        let r: Result<(), u32> = Ok(());
+        r
+    }};
+}
+
+#[cfg(feature = "async")]
+/// The macro generates the service function required.
+/// The service runs on the runtime of the caller, so the runtime argument of the windows macro is not accepted here
+#[macro_export]
+macro_rules! ServiceAsyncMacro {
+    ($entry:ident, $function:ident, $t:ident) => {
+        async fn $entry() {
+            service::run_service_async::<$t, _, _>($function).await;
+        }
+    };
+    ($entry:ident, $function:ident, $t:ident, $config:expr) => {
+        async fn $entry() {
+            let _config: service::RunConfig<$t> = $config;
+            service::run_service_async::<$t, _, _>($function).await;
+        }
+    };
+}
+
+#[derive(Debug)]
+/// Errors that can occur when creating a service
+pub enum CreateError {
+    /// Launchctl does not exist or is not callable for some reason
+    NoLaunchCtl,
+    /// The launchctl command returned an error
+    LaunchCtlFailed,
+    /// Unable to create or write to the launchd plist file
+    FileIoError(std::io::Error),
+    /// The new name given to rename cannot be used for a launchd label
+    InvalidName(crate::InvalidServiceName),
+    /// The binary of the service does not exist, see [ServiceConfig::allow_missing_binary]
+    BinaryNotFound(PathBuf),
+}
+
+impl From<StartStopError> for CreateError {
+    fn from(value: StartStopError) -> Self {
+        match value {
+            StartStopError::NoLaunchCtl => Self::NoLaunchCtl,
+            StartStopError::LaunchCtlFailed | StartStopError::StopTimeout => Self::LaunchCtlFailed,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A placeholder, not currently used
+pub struct Session(String);
+
+/// The configuration for constructing a Service.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ServiceConfig {
+    /// The arguments for the service
+    arguments: Vec<String>,
+    /// The description of the service as presented to the user
+    description: String,
+    /// The path to the service binary
+    binary: PathBuf,
+    /// The username that the service should run as
+    username: Option<String>,
+    /// The path to the configuration data for the service, used as its working directory. When empty, [Service::default_config_path] is used
+    pub config_path: PathBuf,
+    /// Allow creating the service before its binary exists, for staged installs where the binary is put in place afterwards
+    pub allow_missing_binary: bool,
+    /// The environment variables provided to the service
+    pub environment: Vec<(String, String)>,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self::new(Vec::new(), String::new(), PathBuf::new(), None)
+    }
+}
+
+impl ServiceConfig {
+    /// Build a new service config with reasonable defaults.
+    /// # Arguments
+    /// * arguments - The list of arguments to provide to the service
+    /// * description - The description of the service
+    /// * binary - The path to the binary that runs the service
+    /// * username - The username the service runs as
+    pub fn new(
+        arguments: Vec<String>,
+        description: String,
+        binary: PathBuf,
+        username: Option<String>,
+    ) -> Self {
+        Self {
+            arguments,
+            description,
+            binary,
+            config_path: PathBuf::new(),
+            allow_missing_binary: false,
+            username,
+            environment: Vec::new(),
+        }
+    }
+
+    /// The binary as a canonical absolute path, with symlinks and `..` resolved.
+    /// An error is returned when the binary does not exist, unless allow_missing is true, then a relative path is only joined to the current directory
+    fn absolute_binary(&self, allow_missing: bool) -> Result<PathBuf, CreateError> {
+        let binary = if self.binary.is_relative() {
+            std::env::current_dir()
+                .map_err(CreateError::FileIoError)?
+                .join(&self.binary)
+        } else {
+            self.binary.clone()
+        };
+        match std::fs::canonicalize(&binary) {
+            Ok(canonical) if canonical.is_file() => Ok(canonical),
+            _ if allow_missing => Ok(binary),
+            _ => Err(CreateError::BinaryNotFound(binary)),
+        }
+    }
+
+    /// The arguments for the service
+    pub fn arguments(&self) -> &[String] {
+        &self.arguments
+    }
+
+    /// The description of the service
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// The path to the binary that runs the service
+    pub fn binary(&self) -> &std::path::Path {
+        &self.binary
+    }
+
+    /// The username the service runs as
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+}
+
+/// Escape a string for inclusion in the xml of a plist file
+fn escape_xml(value: &str) -> String {
+    let mut s = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => s.push_str("&amp;"),
+            '<' => s.push_str("&lt;"),
+            '>' => s.push_str("&gt;"),
+            '"' => s.push_str("&quot;"),
+            '\'' => s.push_str("&apos;"),
+            c => s.push(c),
+        }
+    }
+    s
+}
+
+/// Undo [escape_xml] and [description_comment]
+fn unescape_xml(value: &str) -> String {
+    value
+        .replace("&#45;", "-")
+        .replace("&#10;", "\n")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// The xml comment holding the description of the service, launchd has no key for a description.
+/// Every `-` is escaped because a comment cannot contain `--`, and every newline so that the comment stays on one line
+fn description_comment(description: &str) -> String {
+    format!(
+        "\t<!-- {} -->\n",
+        escape_xml(description)
+            .replace('-', "&#45;")
+            .replace('\n', "&#10;")
+    )
+}
+
+/// The contents of the element named tag when it is the whole line
+fn element<'a>(line: &'a str, tag: &str) -> Option<&'a str> {
+    line.strip_prefix(&format!("<{}>", tag))?
+        .strip_suffix(&format!("</{}>", tag))
+}
+
+/// Read a plist file written by [Service::create] back into a config
+fn parse_plist(contents: &str) -> ServiceConfig {
+    let mut config = ServiceConfig::default();
+    let mut key = String::new();
+    let mut section = String::new();
+    let mut env_key = None;
+    let mut program = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if let Some(c) = line
+            .strip_prefix("<!-- ")
+            .and_then(|l| l.strip_suffix(" -->"))
+        {
+            config.description = unescape_xml(c);
+        } else if let Some(k) = element(line, "key") {
+            if section == "EnvironmentVariables" {
+                env_key = Some(unescape_xml(k));
+            } else {
+                key = unescape_xml(k);
+            }
+        } else if let Some(v) = element(line, "string") {
+            let v = unescape_xml(v);
+            match (section.as_str(), key.as_str()) {
+                ("EnvironmentVariables", _) => {
+                    if let Some(k) = env_key.take() {
+                        config.environment.push((k, v));
+                    }
+                }
+                ("ProgramArguments", _) => program.push(v),
+                (_, "UserName") => config.username = Some(v),
+                (_, "WorkingDirectory") => config.config_path = PathBuf::from(v),
+                _ => {}
+            }
+        } else if line == "<dict>" || line == "<array>" {
+            section = key.clone();
+        } else if line == "</dict>" || line == "</array>" {
+            section.clear();
+        }
+    }
+    let mut program = program.into_iter();
+    config.binary = program.next().map(PathBuf::from).unwrap_or_default();
+    config.arguments = program.collect();
+    config
+}
+
+/// Check that a name can be used as a launchd label and as the name of its plist file
+fn validate_name(name: &str) -> Result<(), crate::InvalidServiceName> {
+    if name.is_empty() {
+        return Err(crate::InvalidServiceName::Empty);
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| matches!(c, '/' | '\0') || c.is_whitespace())
+    {
+        return Err(crate::InvalidServiceName::InvalidCharacter(c));
+    }
+    Ok(())
+}
+
+/// Find the process id in the output of launchctl list for a single service
+fn listed_pid(out: &[u8]) -> Option<u32> {
+    String::from_utf8_lossy(out).lines().find_map(|l| {
+        l.trim()
+            .strip_prefix("\"PID\" = ")
+            .and_then(|p| p.trim_end_matches(';').parse::<u32>().ok())
+    })
+}
+
+/// Represents a service on the system
+pub struct Service {
+    /// The name of the service, as known by the operating system
+    name: String,
+    /// How long stop waits for the service to stop, None waits as long as launchctl does
+    stop_timeout: Option<std::time::Duration>,
+}
+
+impl Service {
+    /// Construct a new self, this panics when the name is not a valid service name, see [Service::try_new]
+    pub fn new(name: String) -> Self {
+        Self::try_new(&name).unwrap()
+    }
+
+    /// Construct a new self, checking that the name is accepted by launchd
+    pub fn try_new(name: &str) -> Result<Self, crate::InvalidServiceName> {
+        validate_name(name)?;
+        Ok(Self {
+            name: name.to_string(),
+            stop_timeout: None,
+        })
+    }
+
+    /// The name of the service
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Give the service a new name by writing its plist file with the new label and removing the old one.
+    /// The service should be stopped first, since launchd still knows the loaded service under the old name
+    pub fn rename(&mut self, name: &str) -> Result<(), CreateError> {
+        validate_name(name).map_err(CreateError::InvalidName)?;
+        let old = self.plist_path();
+        let contents = std::fs::read_to_string(&old).map_err(CreateError::FileIoError)?;
+        let label = |n: &str| format!("<key>Label</key>\n\t<string>{}</string>", escape_xml(n));
+        let contents = contents.replacen(&label(&self.name), &label(name), 1);
+        self.name = name.to_string();
+        let new = self.plist_path();
+        log::info!("Moving {} to {}", old.display(), new.display());
+        std::fs::write(new, contents).map_err(CreateError::FileIoError)?;
+        std::fs::remove_file(old).map_err(CreateError::FileIoError)
+    }
+
+    /// Returns true when the current process was started by launchd as a service, instead of from a terminal
+    pub fn is_launched_as_service() -> bool {
+        std::os::unix::process::parent_id() == 1
+    }
+
+    /// Set how long stop waits for the service to stop before returning an error
+    pub fn set_stop_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.stop_timeout = timeout;
+    }
+
+    #[cfg(feature = "tracing")]
+    /// Initialize a new tracing subscriber, records from the log crate are forwarded to the subscriber
+    pub fn new_log(&self, level: super::LogLevel) -> Result<(), super::LogInitError> {
+        use tracing_subscriber::layer::SubscriberExt;
+        use tracing_subscriber::util::SubscriberInitExt;
+        tracing_subscriber::registry()
+            .with(level.tracing_level_filter())
+            .with(tracing_subscriber::fmt::layer())
+            .try_init()
+            .map_err(|_| super::LogInitError::AlreadyInitialized)
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    /// Initialize a new log instance
+    pub fn new_log(&self, level: super::LogLevel) -> Result<(), super::LogInitError> {
+        simple_logger::SimpleLogger::new().init()?;
+        log::set_max_level(level.level_filter());
+        Ok(())
+    }
+
+    /// The launchd path for system daemons on macos
+    pub fn launchd_path() -> PathBuf {
+        PathBuf::from("/Library/LaunchDaemons")
+    }
+
+    /// The path of the plist file for the service
+    fn plist_path(&self) -> PathBuf {
+        Self::launchd_path().join(format!("{}.plist", self.name))
+    }
+
+    /// The config path used when the config does not set one, `/Library/Application Support/<name>`
+    pub fn default_config_path(&self) -> PathBuf {
+        PathBuf::from("/Library/Application Support").join(&self.name)
+    }
+
+    /// The config path of the service, as written in its plist file, or the default when the service does not exist
+    pub fn config_path(&self) -> PathBuf {
+        self.query_config()
+            .ok()
+            .map(|c| c.config_path)
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| self.default_config_path())
+    }
+
+    /// Read the config of the service back from its plist file
+    pub fn query_config(&self) -> Result<ServiceConfig, std::io::Error> {
+        std::fs::read_to_string(self.plist_path()).map(|c| parse_plist(&c))
+    }
+
+    /// The config path of the config, or the default when it does not set one
+    fn resolved_config_path(&self, config: &ServiceConfig) -> PathBuf {
+        if config.config_path.as_os_str().is_empty() {
+            self.default_config_path()
+        } else {
+            config.config_path.clone()
+        }
+    }
+
+    /// List the names of the installed system daemons, optionally only those starting with prefix
+    pub fn list(prefix: Option<&str>) -> Result<Vec<String>, std::io::Error> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(Self::launchd_path())? {
+            let path = entry?.path();
+            if path.extension().map(|e| e == "plist").unwrap_or(false) {
+                if let Some(name) = path.file_stem().and_then(|n| n.to_str()) {
+                    if prefix.map(|p| name.starts_with(p)).unwrap_or(true) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// Does the service already exist?
+    pub fn exists(&self) -> bool {
+        self.plist_path().exists()
+    }
+
+    /// Run launchctl with the specified arguments
+    fn launchctl(&self, args: &[&str]) -> Result<(), StartStopError> {
+        let o = std::process::Command::new("launchctl")
+            .args(args)
+            .output()
+            .map_err(|_| StartStopError::NoLaunchCtl)?;
+        if !o.status.success() {
+            Err(StartStopError::LaunchCtlFailed)
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "async")]
+    /// Run launchctl with the specified arguments
+    async fn launchctl_async(&self, args: &[&str]) -> Result<(), StartStopError> {
+        let o = tokio::process::Command::new("launchctl")
+            .args(args)
+            .output()
+            .await
+            .map_err(|_| StartStopError::NoLaunchCtl)?;
+        if !o.status.success() {
+            Err(StartStopError::LaunchCtlFailed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns true when launchd has loaded the service, whether or not it is running
+    fn is_loaded(&self) -> Result<bool, StartStopError> {
+        let o = std::process::Command::new("launchctl")
+            .arg("list")
+            .arg(&self.name)
+            .output()
+            .map_err(|_| StartStopError::NoLaunchCtl)?;
+        Ok(o.status.success())
+    }
+
+    #[cfg(feature = "async")]
+    /// Returns true when launchd has loaded the service, whether or not it is running
+    async fn is_loaded_async(&self) -> Result<bool, StartStopError> {
+        let o = tokio::process::Command::new("launchctl")
+            .arg("list")
+            .arg(&self.name)
+            .output()
+            .await
+            .map_err(|_| StartStopError::NoLaunchCtl)?;
+        Ok(o.status.success())
+    }
+
+    /// Stop the service, stopping a service that is not loaded does nothing and succeeds
+    pub fn stop(&mut self) -> Result<(), StartStopError> {
+        if !self.is_loaded()? {
+            return Ok(());
+        }
+        let pb = self.plist_path();
+        self.launchctl(&["unload", &pb.display().to_string()])?;
+        if let Some(timeout) = self.stop_timeout {
+            let start = std::time::Instant::now();
+            while self.pid()?.is_some() {
+                if start.elapsed() >= timeout {
+                    return Err(StartStopError::StopTimeout);
+                }
+                std::thread::sleep(std::time::Duration::from_millis(250));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    /// Stop the service, stopping a service that is not loaded does nothing and succeeds
+    pub async fn stop_async(&mut self) -> Result<(), StartStopError> {
+        if !self.is_loaded_async().await? {
+            return Ok(());
+        }
+        let pb = self.plist_path();
+        self.launchctl_async(&["unload", &pb.display().to_string()])
+            .await?;
+        if let Some(timeout) = self.stop_timeout {
+            let start = std::time::Instant::now();
+            while self.pid_async().await?.is_some() {
+                if start.elapsed() >= timeout {
+                    return Err(StartStopError::StopTimeout);
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the service, starting a service that is already loaded does nothing and succeeds
+    pub fn start(&mut self) -> Result<(), StartStopError> {
+        if self.is_loaded()? {
+            return Ok(());
+        }
+        let pb = self.plist_path();
+        self.launchctl(&["load", &pb.display().to_string()])
+    }
+
+    #[cfg(feature = "async")]
+    /// Start the service, starting a service that is already loaded does nothing and succeeds
+    pub async fn start_async(&mut self) -> Result<(), StartStopError> {
+        if self.is_loaded_async().await? {
+            return Ok(());
+        }
+        let pb = self.plist_path();
+        self.launchctl_async(&["load", &pb.display().to_string()])
+            .await
+    }
+
+    /// Query the current status of the service
+    pub fn status(&self) -> Result<crate::ServiceStatus, StartStopError> {
+        if !self.exists() {
+            return Ok(crate::ServiceStatus::NotInstalled);
+        }
+        let o = std::process::Command::new("launchctl")
+            .arg("list")
+            .arg(&self.name)
+            .output()
+            .map_err(|_| StartStopError::NoLaunchCtl)?;
+        if !o.status.success() {
+            // The service is not loaded
+            return Ok(crate::ServiceStatus::Stopped);
+        }
+        if String::from_utf8_lossy(&o.stdout).contains("\"PID\" = ") {
+            Ok(crate::ServiceStatus::Running)
+        } else {
+            Ok(crate::ServiceStatus::Stopped)
+        }
+    }
+
+    #[cfg(feature = "async")]
+    /// Query the current status of the service
+    pub async fn status_async(&self) -> Result<crate::ServiceStatus, StartStopError> {
+        if !self.exists() {
+            return Ok(crate::ServiceStatus::NotInstalled);
+        }
+        if self.pid_async().await?.is_some() {
+            Ok(crate::ServiceStatus::Running)
+        } else {
+            Ok(crate::ServiceStatus::Stopped)
+        }
+    }
+
+    /// Returns true when the service is running, any error is treated as not running
+    pub fn is_running(&self) -> bool {
+        matches!(self.status(), Ok(crate::ServiceStatus::Running))
+    }
+
+    /// Get the process id of the service, None when the service is not running
+    pub fn pid(&self) -> Result<Option<u32>, StartStopError> {
+        let o = std::process::Command::new("launchctl")
+            .arg("list")
+            .arg(&self.name)
+            .output()
+            .map_err(|_| StartStopError::NoLaunchCtl)?;
+        if !o.status.success() {
+            // The service is not loaded
+            return Ok(None);
+        }
+        Ok(listed_pid(&o.stdout))
+    }
+
+    #[cfg(feature = "async")]
+    /// Get the process id of the service, None when the service is not running
+    pub async fn pid_async(&self) -> Result<Option<u32>, StartStopError> {
+        let o = tokio::process::Command::new("launchctl")
+            .arg("list")
+            .arg(&self.name)
+            .output()
+            .await
+            .map_err(|_| StartStopError::NoLaunchCtl)?;
+        if !o.status.success() {
+            // The service is not loaded
+            return Ok(None);
+        }
+        Ok(listed_pid(&o.stdout))
+    }
+
+    /// Enable the service so that launchd is allowed to load it at boot
+    pub fn enable(&mut self) -> Result<(), StartStopError> {
+        self.launchctl(&["enable", &format!("system/{}", self.name)])
+    }
+
+    /// Disable the service so that launchd no longer loads it at boot
+    pub fn disable(&mut self) -> Result<(), StartStopError> {
+        self.launchctl(&["disable", &format!("system/{}", self.name)])
+    }
+
+    /// Restart the service, this is done by launchd as a single operation
+    pub fn restart(&mut self) -> Result<(), StartStopError> {
+        self.launchctl(&["kickstart", "-k", &format!("system/{}", self.name)])
+    }
+
+    #[cfg(feature = "async")]
+    /// Restart the service
+    pub async fn restart_async(&mut self) -> Result<(), StartStopError> {
+        let o = tokio::process::Command::new("launchctl")
+            .arg("kickstart")
+            .arg("-k")
+            .arg(format!("system/{}", self.name))
+            .output()
+            .await
+            .map_err(|_| StartStopError::NoLaunchCtl)?;
+        if !o.status.success() {
+            Err(StartStopError::LaunchCtlFailed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Delete the service, unloading it first when launchd has it loaded so that no orphan job is left behind
+    pub fn delete(&mut self) -> Result<(), CreateError> {
+        let pb = self.plist_path();
+        if self.is_loaded()? {
+            self.launchctl(&["unload", &pb.display().to_string()])?;
+        }
+        log::info!("Deleting {}", pb.display());
+        std::fs::remove_file(pb).map_err(CreateError::FileIoError)
+    }
+
+    #[cfg(feature = "async")]
+    /// Delete the service, unloading it first when launchd has it loaded so that no orphan job is left behind
+    pub async fn delete_async(&mut self) -> Result<(), CreateError> {
+        let pb = self.plist_path();
+        if self.is_loaded_async().await? {
+            self.launchctl_async(&["unload", &pb.display().to_string()])
+                .await?;
+        }
+        log::info!("Deleting {}", pb.display());
+        tokio::fs::remove_file(pb)
+            .await
+            .map_err(CreateError::FileIoError)
+    }
+
+    /// Construct the launchd plist file with the specified config
+    fn build_plist_file(&self, config: ServiceConfig) -> String {
+        let config_path = self.resolved_config_path(&config);
+        let mut con = String::new();
+        con.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        con.push_str("<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n");
+        con.push_str("<plist version=\"1.0\">\n<dict>\n");
+        if !config.description.is_empty() {
+            con.push_str(&description_comment(&config.description));
+        }
+        con.push_str("\t<key>Label</key>\n");
+        con.push_str(&format!("\t<string>{}</string>\n", escape_xml(&self.name)));
+        if let Some(user) = config.username {
+            con.push_str("\t<key>UserName</key>\n");
+            con.push_str(&format!("\t<string>{}</string>\n", escape_xml(&user)));
+        }
+        con.push_str("\t<key>WorkingDirectory</key>\n");
+        con.push_str(&format!(
+            "\t<string>{}</string>\n",
+            escape_xml(&config_path.display().to_string())
+        ));
+        if !config.environment.is_empty() {
+            con.push_str("\t<key>EnvironmentVariables</key>\n\t<dict>\n");
+            for (key, value) in &config.environment {
+                con.push_str(&format!("\t\t<key>{}</key>\n", escape_xml(key)));
+                con.push_str(&format!("\t\t<string>{}</string>\n", escape_xml(value)));
+            }
+            con.push_str("\t</dict>\n");
+        }
+        con.push_str("\t<key>ProgramArguments</key>\n\t<array>\n");
+        con.push_str(&format!(
+            "\t\t<string>{}</string>\n",
+            escape_xml(&config.binary.display().to_string())
+        ));
+        for arg in &config.arguments {
+            con.push_str(&format!("\t\t<string>{}</string>\n", escape_xml(arg)));
+        }
+        con.push_str("\t</array>\n");
+        con.push_str("\t<key>RunAtLoad</key>\n\t<true/>\n");
+        con.push_str("</dict>\n</plist>\n");
+        con
+    }
+
+    /// Create the service
+    pub fn create(&mut self, mut config: ServiceConfig) -> Result<(), CreateError> {
+        use std::io::Write;
+        config.binary = config.absolute_binary(config.allow_missing_binary)?;
+        std::fs::create_dir_all(self.resolved_config_path(&config))
+            .map_err(CreateError::FileIoError)?;
+        let con = self.build_plist_file(config);
+        let pb = self.plist_path();
+        log::info!("Saving service file as {}", pb.display());
+        let mut fpw = std::fs::File::create(pb).map_err(CreateError::FileIoError)?;
+        fpw.write_all(con.as_bytes())
+            .map_err(CreateError::FileIoError)?;
+        Ok(())
+    }
+
+    /// Change only the description of the service in its plist file, leaving the rest of the plist as it is
+    pub fn set_description(&mut self, description: &str) -> Result<(), CreateError> {
+        let pb = self.plist_path();
+        let contents = std::fs::read_to_string(&pb).map_err(CreateError::FileIoError)?;
+        let comment = description_comment(description);
+        let contents = match contents.find("\t<!-- ") {
+            Some(start) => {
+                let end = contents[start..]
+                    .find(" -->\n")
+                    .map(|e| start + e + " -->\n".len())
+                    .unwrap_or(contents.len());
+                format!("{}{}{}", &contents[..start], comment, &contents[end..])
+            }
+            None => contents.replacen("<dict>\n", &format!("<dict>\n{}", comment), 1),
+        };
+        std::fs::write(pb, contents).map_err(CreateError::FileIoError)
+    }
+
+    /// Create the service when it does not exist, otherwise rewrite its plist file with the config.
+    /// launchd uses the new plist the next time the service is loaded
+    pub fn ensure(&mut self, config: ServiceConfig) -> Result<crate::EnsureOutcome, CreateError> {
+        let existed = self.exists();
+        self.create(config)?;
+        Ok(if existed {
+            crate::EnsureOutcome::Updated
+        } else {
+            crate::EnsureOutcome::Created
+        })
+    }
+
+    #[cfg(feature = "async")]
+    /// Create the service
+    pub async fn create_async(&mut self, mut config: ServiceConfig) -> Result<(), CreateError> {
+        use tokio::io::AsyncWriteExt;
+
+        config.binary = config.absolute_binary(config.allow_missing_binary)?;
+        tokio::fs::create_dir_all(self.resolved_config_path(&config))
+            .await
+            .map_err(CreateError::FileIoError)?;
+        let con = self.build_plist_file(config);
+        let pb = self.plist_path();
+        log::info!("Saving service file as {}", pb.display());
+        let mut fpw = tokio::fs::File::create(pb)
+            .await
+            .map_err(CreateError::FileIoError)?;
+        fpw.write_all(con.as_bytes())
+            .await
+            .map_err(CreateError::FileIoError)?;
+        Ok(())
+    }
+
+    /// Run the service function in the foreground, ctrl-c stops the service
+    pub fn run_standalone<T: Send + 'static>(&self, service_main: ServiceFn<T>) -> u32 {
+        run_with_signals(service_main, true)
+    }
+
+    /// Run the required dispatch code
+    pub fn dispatch(&self, service_main: DispatchFn) -> Result<(), u32> {
+        service_main();
+        Ok(())
+    }
+}
+
+/// Runs the main service function, delivering SIGTERM and SIGINT to it as [crate::ServiceEvent::Stop] and SIGHUP as [crate::ServiceEvent::Reload].
+/// For a standalone run without any signal handling, the service function can be called directly with None for both channels.
+pub fn run_service<T: Send + 'static>(service_main: ServiceFn<T>) {
+    let code = run_with_signals(service_main, false);
+    if code != 0 {
+        std::process::exit(code as i32);
+    }
+}
+
+/// Run the service function with the signals delivered as service events, returning its exit code
+fn run_with_signals<T: Send + 'static>(service_main: ServiceFn<T>, standalone_mode: bool) -> u32 {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let tx2: std::sync::mpsc::Sender<crate::ServiceEvent<T>> = tx.clone();
+    let signals = signal_hook::iterator::Signals::new([
+        signal_hook::consts::SIGTERM,
+        signal_hook::consts::SIGINT,
+        signal_hook::consts::SIGHUP,
+    ]);
+    let (signal_handle, signal_thread) = match signals {
+        Ok(mut signals) => {
+            let handle = signals.handle();
+            let thread = std::thread::spawn(move || {
+                for signal in signals.forever() {
+                    let event = if signal == signal_hook::consts::SIGHUP {
+                        crate::ServiceEvent::Reload
+                    } else {
+                        crate::ServiceEvent::Stop
+                    };
+                    let _ = tx.send(event);
+                }
+            });
+            (Some(handle), Some(thread))
+        }
+        Err(e) => {
+            log::error!("Unable to register signal handlers: {:?}", e);
+            (None, None)
+        }
+    };
+    let args = std::env::args().collect();
+    let code = service_main(Some(rx), Some(tx2), args, standalone_mode).report();
+    if let Some(handle) = signal_handle {
+        handle.close();
+    }
+    if let Some(thread) = signal_thread {
+        let _ = thread.join();
+    }
+    code
+}
+
+#[cfg(feature = "async")]
+/// Runs the async service function, delivering SIGTERM and SIGINT to it as [crate::ServiceEvent::Stop] and SIGHUP as [crate::ServiceEvent::Reload].
+/// The service function should return once it receives [crate::ServiceEvent::Stop], a non zero exit code becomes the exit code of the process.
+pub async fn run_service_async<T, F, Fut>(service_main: F)
+where
+    T: Send + 'static,
+    F: FnOnce(
+        tokio::sync::mpsc::Receiver<crate::ServiceEvent<T>>,
+        tokio::sync::mpsc::Sender<crate::ServiceEvent<T>>,
+        Vec<String>,
+        bool,
+    ) -> Fut,
+    Fut: std::future::Future<Output = crate::ServiceExit>,
+{
+    use tokio::signal::unix::{signal, SignalKind};
+    let (tx, rx) = tokio::sync::mpsc::channel(10);
+    let tx2: tokio::sync::mpsc::Sender<crate::ServiceEvent<T>> = tx.clone();
+    let signals = (|| {
+        Ok::<_, std::io::Error>((
+            signal(SignalKind::terminate())?,
+            signal(SignalKind::interrupt())?,
+            signal(SignalKind::hangup())?,
+        ))
+    })();
+    let signal_task = match signals {
+        Ok((mut term, mut int, mut hup)) => Some(tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    _ = term.recv() => crate::ServiceEvent::Stop,
+                    _ = int.recv() => crate::ServiceEvent::Stop,
+                    _ = hup.recv() => crate::ServiceEvent::Reload,
+                };
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        })),
+        Err(e) => {
+            log::error!("Unable to register signal handlers: {:?}", e);
+            None
+        }
+    };
+    let args = std::env::args().collect();
+    let code = service_main(rx, tx2, args, false).await.report();
+    if let Some(task) = signal_task {
+        task.abort();
+    }
+    if code != 0 {
+        std::process::exit(code as i32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plist_round_trip() {
+        let arguments = vec![
+            "--name=<a & b>".to_string(),
+            "\"quoted\" 'single'".to_string(),
+            "a--b-".to_string(),
+        ];
+        let mut config = ServiceConfig::new(
+            arguments.clone(),
+            "Tom & Jerry's <\"service\"> -- ends in -".to_string(),
+            PathBuf::from("/usr/local/bin/a&b"),
+            Some("<user>".to_string()),
+        );
+        config.config_path = PathBuf::from("/Library/Application Support/a<b>&c");
+        config.environment = vec![("KEY&<1>".to_string(), "\"value\" & 'more'".to_string())];
+        let service = Service::new("example".into());
+        let plist = service.build_plist_file(config);
+        assert!(!plist.contains("ServiceDescription"));
+        let comment = plist.lines().find_map(|l| l.trim().strip_prefix("<!--"));
+        assert!(!comment.unwrap().trim_end_matches("-->").contains("--"));
+
+        let parsed = parse_plist(&plist);
+        assert_eq!(parsed.arguments, arguments);
+        assert_eq!(
+            parsed.description,
+            "Tom & Jerry's <\"service\"> -- ends in -"
+        );
+        assert_eq!(parsed.binary, PathBuf::from("/usr/local/bin/a&b"));
+        assert_eq!(parsed.username.as_deref(), Some("<user>"));
+        assert_eq!(
+            parsed.config_path,
+            PathBuf::from("/Library/Application Support/a<b>&c")
+        );
+        assert_eq!(
+            parsed.environment,
+            vec![("KEY&<1>".to_string(), "\"value\" & 'more'".to_string())]
+        );
+    }
+
+    #[test]
+    fn plist_without_description_has_no_comment() {
+        let config = ServiceConfig::new(Vec::new(), String::new(), PathBuf::from("/bin/a"), None);
+        let plist = Service::new("example".into()).build_plist_file(config);
+        assert!(!plist.contains("<!--"));
+        let parsed = parse_plist(&plist);
+        assert!(parsed.description.is_empty());
+        assert!(parsed.arguments.is_empty());
+        assert_eq!(parsed.binary, PathBuf::from("/bin/a"));
+    }
+}
@@ -0,0 +1,375 @@
+//! An in memory service backend for testing code that manages services, without a service manager.
+//! With the mock feature [crate::Service] is this service. It only covers new, try_new, name, create, start, stop, enable, disable, delete, status, exists, and is_running, so code using other methods of the platform service, such as restart, update, or the async methods, does not build against it and has to use [PlatformService] for those.
+//! It returns the error types of the platform. Running the service, with dispatch, run_standalone, and new_log, is left to the service of the platform, which stays available as [PlatformService].
+//! The state of the services is kept per thread, so that tests running in parallel do not see each other's services.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+cfg_if::cfg_if! {
+    if #[cfg(windows)] {
+        /// The service of the platform, which the mock service replaces as [crate::Service]
+        pub use crate::windows::Service as PlatformService;
+    } else if #[cfg(target_os = "macos")] {
+        /// The service of the platform, which the mock service replaces as [crate::Service]
+        pub use crate::macos::Service as PlatformService;
+    } else if #[cfg(target_os = "android")] {
+        /// The service of the platform, which the mock service replaces as [crate::Service]
+        pub use crate::android::Service as PlatformService;
+    } else {
+        /// The service of the platform, which the mock service replaces as [crate::Service]
+        pub use crate::linux::Service as PlatformService;
+    }
+}
+
+/// The operations on a mock service that are recorded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// The service was created
+    Create,
+    /// The service was started
+    Start,
+    /// The service was stopped
+    Stop,
+    /// The service was deleted
+    Delete,
+    /// The service was enabled
+    Enable,
+    /// The service was disabled
+    Disable,
+}
+
+/// The windows error code of an operation that was set to fail with [Service::fail_next]
+#[cfg(windows)]
+pub const INJECTED_ERROR: u32 = winapi::shared::winerror::ERROR_GEN_FAILURE;
+
+/// Why a mock operation failed, this is turned into the error of the platform
+#[derive(Debug)]
+enum Failure {
+    /// The service has not been created
+    NotInstalled,
+    /// The service was already created
+    AlreadyExists,
+    /// The operation was set to fail with [Service::fail_next]
+    Injected(Operation),
+}
+
+impl Failure {
+    /// The message for the platform errors that carry one
+    #[cfg(not(windows))]
+    fn message(&self) -> String {
+        match self {
+            Failure::NotInstalled => "the mock service is not installed".to_string(),
+            Failure::AlreadyExists => "the mock service already exists".to_string(),
+            Failure::Injected(op) => format!("injected failure of {:?}", op),
+        }
+    }
+
+    /// The failure as an io error
+    #[cfg(not(windows))]
+    fn io_error(self) -> std::io::Error {
+        let kind = match self {
+            Failure::NotInstalled => std::io::ErrorKind::NotFound,
+            Failure::AlreadyExists => std::io::ErrorKind::AlreadyExists,
+            Failure::Injected(_) => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, self.message())
+    }
+}
+
+/// The error types of the platform that a mock failure is turned into
+trait FromFailure {
+    /// Turn the failure into the error that the service of the platform returns in its place
+    fn from_failure(failure: Failure) -> Self;
+}
+
+#[cfg(windows)]
+impl FromFailure for crate::ServiceError {
+    fn from_failure(failure: Failure) -> Self {
+        match failure {
+            Failure::NotInstalled => Self::ServiceDoesNotExist,
+            Failure::AlreadyExists => Self::Other(winapi::shared::winerror::ERROR_SERVICE_EXISTS),
+            Failure::Injected(_) => Self::Other(INJECTED_ERROR),
+        }
+    }
+}
+
+#[cfg(windows)]
+impl FromFailure for crate::StartServiceError {
+    fn from_failure(failure: Failure) -> Self {
+        Self::WindowsError(crate::ServiceError::from_failure(failure))
+    }
+}
+
+#[cfg(windows)]
+impl FromFailure for crate::CreateServiceError {
+    fn from_failure(failure: Failure) -> Self {
+        Self::WindowsError(crate::ServiceError::from_failure(failure))
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl FromFailure for crate::StartStopError {
+    fn from_failure(_failure: Failure) -> Self {
+        Self::LaunchCtlFailed
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl FromFailure for crate::CreateError {
+    fn from_failure(failure: Failure) -> Self {
+        Self::FileIoError(failure.io_error())
+    }
+}
+
+#[cfg(target_os = "android")]
+impl FromFailure for crate::StartStopError {
+    fn from_failure(_failure: Failure) -> Self {
+        Self::Unsupported
+    }
+}
+
+#[cfg(target_os = "android")]
+impl FromFailure for crate::CreateError {
+    fn from_failure(_failure: Failure) -> Self {
+        Self::Unsupported
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl FromFailure for crate::StartStopError {
+    fn from_failure(failure: Failure) -> Self {
+        Self::SystemCtlFailed {
+            code: Some(1),
+            stderr: failure.message(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl FromFailure for crate::CreateError {
+    fn from_failure(failure: Failure) -> Self {
+        match failure {
+            Failure::AlreadyExists => Self::FileIoError(failure.io_error()),
+            failure => Self::SystemCtlFailed {
+                code: Some(1),
+                stderr: failure.message(),
+            },
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+impl FromFailure for std::io::Error {
+    fn from_failure(failure: Failure) -> Self {
+        failure.io_error()
+    }
+}
+
+/// The state of one mock service
+#[derive(Default)]
+struct MockState {
+    /// Whether the service has been created and not deleted
+    installed: bool,
+    /// The status the service reports while it is installed
+    status: Option<crate::ServiceStatus>,
+    /// The operations done on the service, in order
+    calls: Vec<Operation>,
+    /// Operations that fail the next time they are done
+    failures: Vec<Operation>,
+}
+
+thread_local! {
+    /// The mock services of the current thread, by name
+    static SERVICES: RefCell<HashMap<String, MockState>> = RefCell::new(HashMap::new());
+}
+
+/// A mock service, with the same methods as the service of the platform
+#[derive(Clone, Debug)]
+pub struct Service {
+    /// The name of the service
+    name: String,
+}
+
+impl Service {
+    /// Construct a new self, this panics when the name is not a valid service name, see [Service::try_new]
+    pub fn new(name: String) -> Self {
+        Self::try_new(&name).unwrap()
+    }
+
+    /// Construct a new self, checking the name the same way as the service of the platform
+    pub fn try_new(name: &str) -> Result<Self, crate::InvalidServiceName> {
+        PlatformService::try_new(name)?;
+        Ok(Self {
+            name: name.to_string(),
+        })
+    }
+
+    /// The name of the service
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The service of the platform with the same name, for running the service
+    fn platform(&self) -> PlatformService {
+        PlatformService::new(self.name.clone())
+    }
+
+    /// Returns true when the current process was started by the service manager of the platform
+    pub fn is_launched_as_service() -> bool {
+        PlatformService::is_launched_as_service()
+    }
+
+    /// Initialize logging the same way as the service of the platform
+    pub fn new_log(&self, level: crate::LogLevel) -> Result<(), crate::LogInitError> {
+        self.platform().new_log(level)
+    }
+
+    /// Run the service function in the foreground, using the service of the platform
+    pub fn run_standalone<T: Send + 'static>(&self, service_main: crate::ServiceFn<T>) -> u32 {
+        self.platform().run_standalone(service_main)
+    }
+
+    /// Run the dispatch code of the service of the platform
+    pub fn dispatch(&self, service_main: crate::DispatchFn) -> Result<(), u32> {
+        self.platform().dispatch(service_main)
+    }
+
+    /// Run f on the state of the service
+    fn with_state<R>(&self, f: impl FnOnce(&mut MockState) -> R) -> R {
+        SERVICES.with(|s| f(s.borrow_mut().entry(self.name.clone()).or_default()))
+    }
+
+    /// Record the operation, failing it when it was set to fail or when the service must be installed and is not
+    fn record<E: FromFailure>(&self, op: Operation, needs_install: bool) -> Result<(), E> {
+        self.with_state(|state| {
+            state.calls.push(op);
+            if let Some(i) = state.failures.iter().position(|f| *f == op) {
+                state.failures.remove(i);
+                return Err(E::from_failure(Failure::Injected(op)));
+            }
+            if needs_install && !state.installed {
+                return Err(E::from_failure(Failure::NotInstalled));
+            }
+            Ok(())
+        })
+    }
+
+    /// Create the service, the config is accepted and ignored
+    pub fn create(
+        &mut self,
+        _config: crate::ServiceConfig,
+    ) -> Result<(), crate::CreateServiceFailure> {
+        self.record::<crate::CreateServiceFailure>(Operation::Create, false)?;
+        self.with_state(|state| {
+            if state.installed {
+                return Err(FromFailure::from_failure(Failure::AlreadyExists));
+            }
+            state.installed = true;
+            state.status = Some(crate::ServiceStatus::Stopped);
+            Ok(())
+        })
+    }
+
+    /// Start the service, its status becomes running
+    pub fn start(&mut self) -> Result<(), crate::StartError> {
+        self.record::<crate::StartError>(Operation::Start, true)?;
+        self.with_state(|state| state.status = Some(crate::ServiceStatus::Running));
+        Ok(())
+    }
+
+    /// Stop the service, its status becomes stopped
+    pub fn stop(&mut self) -> Result<(), crate::ControlError> {
+        self.record::<crate::ControlError>(Operation::Stop, true)?;
+        self.with_state(|state| state.status = Some(crate::ServiceStatus::Stopped));
+        Ok(())
+    }
+
+    /// Enable the service
+    pub fn enable(&mut self) -> Result<(), crate::ControlError> {
+        self.record(Operation::Enable, true)
+    }
+
+    /// Disable the service
+    pub fn disable(&mut self) -> Result<(), crate::ControlError> {
+        self.record(Operation::Disable, true)
+    }
+
+    /// Delete the service
+    pub fn delete(&mut self) -> Result<(), crate::DeleteError> {
+        self.record::<crate::DeleteError>(Operation::Delete, true)?;
+        self.with_state(|state| {
+            state.installed = false;
+            state.status = None;
+        });
+        Ok(())
+    }
+
+    /// Does the service already exist?
+    pub fn exists(&self) -> bool {
+        self.with_state(|state| state.installed)
+    }
+
+    /// Query the current status of the service, not installed when it has not been created
+    pub fn status(&self) -> Result<crate::ServiceStatus, crate::ControlError> {
+        Ok(self.with_state(|state| match state.status {
+            Some(status) if state.installed => status,
+            _ => crate::ServiceStatus::NotInstalled,
+        }))
+    }
+
+    /// Returns true when the service is running
+    pub fn is_running(&self) -> bool {
+        matches!(self.status(), Ok(crate::ServiceStatus::Running))
+    }
+
+    /// Set the status that the service reports until the next start or stop, it has no effect until the service is created
+    pub fn set_status(&self, status: crate::ServiceStatus) {
+        self.with_state(|state| state.status = Some(status));
+    }
+
+    /// Make the next time the operation is done on the service fail, with the error the platform reports for a failed service manager call
+    pub fn fail_next(&self, op: Operation) {
+        self.with_state(|state| state.failures.push(op));
+    }
+
+    /// The operations done on the service so far, in order, including the ones that failed
+    pub fn calls(&self) -> Vec<Operation> {
+        self.with_state(|state| state.calls.clone())
+    }
+
+    /// Forget every mock service of the current thread
+    pub fn reset_all() {
+        SERVICES.with(|s| s.borrow_mut().clear());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deploy_undoes_the_steps_before_a_failed_start() {
+        let mut service = crate::Service::new("mock-deploy".into());
+        service.fail_next(Operation::Start);
+        let config = crate::ServiceConfigBuilder::new()
+            .binary(std::path::PathBuf::from("/usr/bin/example"))
+            .build();
+        assert!(matches!(
+            service.deploy(config),
+            Err(crate::DeployError::Start(_))
+        ));
+        assert_eq!(
+            service.calls(),
+            [
+                Operation::Create,
+                Operation::Enable,
+                Operation::Start,
+                Operation::Disable,
+                Operation::Delete
+            ]
+        );
+        assert!(!service.exists());
+        assert!(crate::Service::try_new("").is_err());
+    }
+}
@@ -2,6 +2,8 @@
 
 use std::path::PathBuf;
 
+use crate::ServiceFn;
+
 use std::os::windows::ffi::OsStrExt;
 
 use std::sync::{Arc, Mutex};
@@ -17,28 +19,139 @@ use winapi::um::winsvc::SERVICE_RUNNING;
 use winapi::um::winsvc::SERVICE_START_PENDING;
 
 /// A handle for setting the status of the service
-pub struct ServiceStatusHandle(winapi::um::winsvc::SERVICE_STATUS_HANDLE);
+pub struct ServiceStatusHandle(winapi::um::winsvc::SERVICE_STATUS_HANDLE, DWORD);
 
 #[derive(Debug)]
 /// Errors that can occur starting the service
 pub enum StartServiceError {
-    /// A windows specific error code
-    WindowsError(DWORD),
+    /// A windows specific error
+    WindowsError(ServiceError),
     /// The service could not be brought into the started state
     FailedToStart(DWORD),
+    /// The service was still starting when the timeout of the poll config elapsed
+    StartTimeout,
 }
 
 #[derive(Debug)]
 /// Errors that can occur creating the service
 pub enum CreateServiceError {
-    /// A windows specific error code
-    WindowsError(DWORD),
+    /// A windows specific error
+    WindowsError(ServiceError),
+    /// The config path could not be created
+    FileIoError(std::io::Error),
+    /// Windows cannot rename a service, it must be deleted and created again with the new name
+    RenameNotSupported,
+    /// The binary of the service does not exist, see [ServiceConfig::allow_missing_binary]
+    BinaryNotFound(PathBuf),
+    /// The path of the binary is not valid unicode, so it cannot be put on the command line of the service
+    InvalidBinaryPath(PathBuf),
+    /// The event log source of the service could not be registered
+    EventLogError(eventlog::Error),
+}
+
+#[derive(Debug)]
+/// Errors that can occur restarting the service
+pub enum RestartServiceError {
+    /// A windows specific error
+    WindowsError(ServiceError),
+    /// The service did not reach the stopped state in time
+    StopTimeout,
+    /// The service did not reach the running state in time
+    StartTimeout,
+    /// The service stopped again while starting, with the state it ended up in
+    FailedToStart(DWORD),
+}
+
+/// The time that start, stop, and restart wait for the service to change state by default, see [PollConfig]
+const DEFAULT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An error code reported by windows, with names for the common cases
+pub enum ServiceError {
+    /// The caller does not have the access required for the operation, it must be run elevated as an administrator
+    NeedsElevation,
+    /// The specified service is not installed
+    ServiceDoesNotExist,
+    /// The service is already running
+    ServiceAlreadyRunning,
+    /// The service did not stop within the stop timeout
+    StopTimeout,
+    /// The service was deleted while it was still running, windows removes it once it stops
+    MarkedForDelete,
+    /// Any other windows error code
+    Other(DWORD),
+}
+
+impl ServiceError {
+    /// Retrieve the last error that windows reported on the calling thread
+    pub fn last() -> Self {
+        Self::from(unsafe { winapi::um::errhandlingapi::GetLastError() })
+    }
+
+    /// The raw windows error code
+    pub fn code(&self) -> DWORD {
+        match self {
+            ServiceError::NeedsElevation => winapi::shared::winerror::ERROR_ACCESS_DENIED,
+            ServiceError::ServiceDoesNotExist => {
+                winapi::shared::winerror::ERROR_SERVICE_DOES_NOT_EXIST
+            }
+            ServiceError::ServiceAlreadyRunning => {
+                winapi::shared::winerror::ERROR_SERVICE_ALREADY_RUNNING
+            }
+            ServiceError::StopTimeout => winapi::shared::winerror::ERROR_SERVICE_REQUEST_TIMEOUT,
+            ServiceError::MarkedForDelete => {
+                winapi::shared::winerror::ERROR_SERVICE_MARKED_FOR_DELETE
+            }
+            ServiceError::Other(e) => *e,
+        }
+    }
+
+    /// The human readable message for the error, as provided by windows
+    pub fn message(&self) -> String {
+        let mut buffer = [0u16; 512];
+        let len = unsafe {
+            winapi::um::winbase::FormatMessageW(
+                winapi::um::winbase::FORMAT_MESSAGE_FROM_SYSTEM
+                    | winapi::um::winbase::FORMAT_MESSAGE_IGNORE_INSERTS,
+                std::ptr::null(),
+                self.code(),
+                0,
+                buffer.as_mut_ptr(),
+                buffer.len() as DWORD,
+                std::ptr::null_mut(),
+            )
+        };
+        String::from_utf16_lossy(&buffer[..len as usize])
+            .trim_end()
+            .to_string()
+    }
+}
+
+impl From<DWORD> for ServiceError {
+    fn from(value: DWORD) -> Self {
+        match value {
+            winapi::shared::winerror::ERROR_ACCESS_DENIED => Self::NeedsElevation,
+            winapi::shared::winerror::ERROR_SERVICE_DOES_NOT_EXIST => Self::ServiceDoesNotExist,
+            winapi::shared::winerror::ERROR_SERVICE_ALREADY_RUNNING => Self::ServiceAlreadyRunning,
+            // ERROR_SERVICE_REQUEST_TIMEOUT stays Other, windows also reports it for a service that does not answer a start request
+            winapi::shared::winerror::ERROR_SERVICE_MARKED_FOR_DELETE => Self::MarkedForDelete,
+            e => Self::Other(e),
+        }
+    }
+}
+
+impl std::fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (error {})", self.message(), self.code())
+    }
 }
 
+impl std::error::Error for ServiceError {}
+
 impl ServiceStatusHandle {
-    /// Create a new handle
-    pub fn new(a: winapi::um::winsvc::SERVICE_STATUS_HANDLE) -> Self {
-        Self(a)
+    /// Create a new handle, with the controls that the service reports as accepted
+    pub fn new(a: winapi::um::winsvc::SERVICE_STATUS_HANDLE, controls_accepted: DWORD) -> Self {
+        Self(a, controls_accepted)
     }
 }
 
@@ -46,30 +159,288 @@ unsafe impl Send for ServiceStatusHandle {}
 
 lazy_static::lazy_static! {
     /// The service handle for updating the status of the service that is running
-    pub static ref SERVICE_HANDLE : Arc<Mutex<ServiceStatusHandle>> = Arc::new(Mutex::new(ServiceStatusHandle(std::ptr::null_mut())));
+    pub static ref SERVICE_HANDLE : Arc<Mutex<ServiceStatusHandle>> = Arc::new(Mutex::new(ServiceStatusHandle(std::ptr::null_mut(), 0)));
+}
+
+impl<T> crate::RunConfig<T> {
+    /// The name to register the control handler of the service under, the first argument from windows or the configured service name when there are no arguments.
+    /// Windows ignores the name for a service that runs in its own process, so an empty name is used when neither is present
+    pub fn handler_name(&self, args: &[String]) -> String {
+        args.first()
+            .or(self.service_name.as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The controls that the service reports to windows as accepted
+    pub fn controls_accepted(&self) -> DWORD {
+        let mut controls =
+            winapi::um::winsvc::SERVICE_ACCEPT_STOP | winapi::um::winsvc::SERVICE_ACCEPT_SHUTDOWN;
+        if self.accept_pause_continue {
+            controls |= winapi::um::winsvc::SERVICE_ACCEPT_PAUSE_CONTINUE;
+        }
+        if self.accept_session_changes {
+            controls |= winapi::um::winsvc::SERVICE_ACCEPT_SESSIONCHANGE;
+        }
+        if self.accept_preshutdown {
+            controls |= winapi::um::winsvc::SERVICE_ACCEPT_PRESHUTDOWN;
+        }
+        if self.accept_power_events {
+            controls |= winapi::um::winsvc::SERVICE_ACCEPT_POWEREVENT;
+        }
+        controls
+    }
+}
+
+lazy_static::lazy_static! {
+    /// The map from custom control codes to custom messages of the running service, from [crate::RunConfig::custom_control]
+    static ref CUSTOM_CONTROL: Mutex<Option<Arc<dyn std::any::Any + Send + Sync>>> = Mutex::new(None);
+}
+
+/// When the running service was told to stop, used for the stop drain timeout of [crate::RunConfig]
+static STOP_REQUESTED: Mutex<Option<std::time::Instant>> = Mutex::new(None);
+
+/// The service specific exit code reported when the service function does not return within the stop drain timeout
+pub const DRAIN_TIMEOUT_EXIT_CODE: DWORD = winapi::shared::winerror::ERROR_SERVICE_REQUEST_TIMEOUT;
+
+/// Forget the stop request of an earlier run, this is done before the control handler of the service is registered so that no stop request is lost
+pub fn reset_stop_requested() {
+    *STOP_REQUESTED.lock().unwrap() = None;
+}
+
+/// Remember the first time that the running service is told to stop
+fn note_stop_requested() {
+    STOP_REQUESTED
+        .lock()
+        .unwrap()
+        .get_or_insert_with(std::time::Instant::now);
+}
+
+/// Returns true once the service was told to stop longer than timeout ago
+fn drain_expired(timeout: Option<std::time::Duration>) -> bool {
+    match (timeout, *STOP_REQUESTED.lock().unwrap()) {
+        (Some(timeout), Some(at)) => at.elapsed() >= timeout,
+        _ => false,
+    }
+}
+
+#[cfg(feature = "async")]
+/// Run the future of the async service function, giving up on it when it does not finish within the drain timeout after the service is told to stop.
+/// This is used by [ServiceAsyncMacro]
+pub async fn run_with_drain_timeout<F: std::future::Future<Output = crate::ServiceExit>>(
+    service_main: F,
+    timeout: Option<std::time::Duration>,
+) -> u32 {
+    let expired = async {
+        if timeout.is_none() {
+            std::future::pending::<()>().await;
+        }
+        while !drain_expired(timeout) {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    };
+    tokio::select! {
+        exit = service_main => exit.report(),
+        _ = expired => {
+            log::error!("The service did not stop within the stop drain timeout");
+            DRAIN_TIMEOUT_EXIT_CODE
+        }
+    }
+}
+
+/// Remember the map for custom control codes from the config of the service that is starting
+pub fn set_custom_control<T: 'static>(config: &crate::RunConfig<T>) {
+    *CUSTOM_CONTROL.lock().unwrap() = config
+        .custom_control
+        .map(|map| Arc::new(map) as Arc<dyn std::any::Any + Send + Sync>);
+}
+
+/// Turn a custom control code into the custom message of the running service
+fn custom_event<T: 'static>(code: DWORD) -> Option<T> {
+    let custom = CUSTOM_CONTROL.lock().unwrap();
+    let Some(map) = custom.as_ref()?.downcast_ref::<fn(u32) -> Option<T>>() else {
+        log::error!(
+            "The custom control map is not for messages of type {}, control code {} is ignored",
+            std::any::type_name::<T>(),
+            code
+        );
+        return None;
+    };
+    map(code)
 }
 
+lazy_static::lazy_static! {
+    /// Sends the stop event to a service that is running in standalone mode
+    static ref CONSOLE_STOP: Mutex<Option<Box<dyn Fn() + Send>>> = Mutex::new(None);
+}
+
+/// The console control handler used in standalone mode, this turns ctrl-c into a stop event
+unsafe extern "system" fn console_ctrl_handler(
+    ctrl_type: DWORD,
+) -> winapi::shared::minwindef::BOOL {
+    match ctrl_type {
+        winapi::um::wincon::CTRL_C_EVENT
+        | winapi::um::wincon::CTRL_BREAK_EVENT
+        | winapi::um::wincon::CTRL_CLOSE_EVENT => {
+            if let Some(stop) = CONSOLE_STOP.lock().unwrap().as_ref() {
+                stop();
+            }
+            winapi::shared::minwindef::TRUE
+        }
+        _ => winapi::shared::minwindef::FALSE,
+    }
+}
+
+#[link(name = "advapi32")]
+extern "system" {
+    /// Open an event log for reading, this is not provided by winapi
+    fn OpenEventLogW(
+        server_name: winapi::um::winnt::LPCWSTR,
+        source_name: winapi::um::winnt::LPCWSTR,
+    ) -> winapi::um::winnt::HANDLE;
+    /// Read records from an event log, this is not provided by winapi
+    fn ReadEventLogW(
+        event_log: winapi::um::winnt::HANDLE,
+        read_flags: DWORD,
+        record_offset: DWORD,
+        buffer: winapi::shared::minwindef::LPVOID,
+        bytes_to_read: DWORD,
+        bytes_read: *mut DWORD,
+        min_bytes_needed: *mut DWORD,
+    ) -> winapi::shared::minwindef::BOOL;
+    /// Close an event log opened with OpenEventLogW, this is not provided by winapi
+    fn CloseEventLog(event_log: winapi::um::winnt::HANDLE) -> winapi::shared::minwindef::BOOL;
+}
+
+#[link(name = "wtsapi32")]
+extern "system" {
+    /// Query information about a terminal services session, this is not provided by winapi
+    fn WTSQuerySessionInformationW(
+        server: winapi::um::winnt::HANDLE,
+        session_id: DWORD,
+        info_class: u32,
+        buffer: *mut winapi::um::winnt::LPWSTR,
+        bytes_returned: *mut DWORD,
+    ) -> winapi::shared::minwindef::BOOL;
+    /// Free memory returned by the terminal services api, this is not provided by winapi
+    fn WTSFreeMemory(memory: winapi::shared::minwindef::LPVOID);
+}
+
+/// The custom control code that is delivered to the service as [crate::ServiceEvent::Reload]
+pub const SERVICE_CONTROL_RELOAD: DWORD = 128;
+
 /// The function used to dispatch a windows service.
 pub type DispatchFn =
     extern "system" fn(winapi::shared::minwindef::DWORD, *mut winapi::um::winnt::LPWSTR);
 
-/// The type for the service function.
-pub type ServiceFn<T> = fn(
-    rx: Option<std::sync::mpsc::Receiver<crate::ServiceEvent<T>>>,
-    tx: Option<std::sync::mpsc::Sender<crate::ServiceEvent<T>>>,
-);
+/// How a windows session is attached to the computer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionKind {
+    /// Session 0, where services run, no user logs on to this session
+    Service,
+    /// A session on the physical console
+    Console,
+    /// A session over remote desktop
+    Remote,
+}
 
-/// The type for the service function. Receives commands from windows to control the actual service. This is the async version of ServiceFn.
-pub type ServiceFnAsync<T> = fn(
-    rx: std::sync::mpsc::Receiver<crate::ServiceEvent<T>>,
-    tx: std::sync::mpsc::Sender<crate::ServiceEvent<T>>,
-    args: Vec<String>,
-    standalone_mode: bool,
-) -> u32;
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The session for windows service messages, with details about the user that are read when the message is received
+pub struct Session {
+    /// The windows session id
+    id: u32,
+    /// The name of the user logged on to the session
+    username: Option<String>,
+    /// The domain of the user logged on to the session
+    domain: Option<String>,
+    /// How the session is attached to the computer
+    kind: SessionKind,
+}
 
-#[derive(Debug)]
-/// The session id for windows service messages
-pub struct Session(u32);
+/// The WTSUserName value of WTS_INFO_CLASS
+const WTS_USER_NAME: u32 = 5;
+/// The WTSDomainName value of WTS_INFO_CLASS
+const WTS_DOMAIN_NAME: u32 = 7;
+/// The WTSClientProtocolType value of WTS_INFO_CLASS
+const WTS_CLIENT_PROTOCOL_TYPE: u32 = 16;
+
+impl Session {
+    /// Look up the details of the session with the given id
+    fn query(id: u32) -> Self {
+        let kind = if id == 0 {
+            SessionKind::Service
+        } else {
+            // The protocol is 0 for the console, and 2 for remote desktop
+            let protocol = query_session_info(id, WTS_CLIENT_PROTOCOL_TYPE, |buffer, bytes| {
+                if bytes as usize >= std::mem::size_of::<u16>() {
+                    unsafe { *(buffer as *const u16) }
+                } else {
+                    0
+                }
+            });
+            match protocol {
+                Some(0) | None => SessionKind::Console,
+                Some(_) => SessionKind::Remote,
+            }
+        };
+        Self {
+            id,
+            username: query_session_string(id, WTS_USER_NAME),
+            domain: query_session_string(id, WTS_DOMAIN_NAME),
+            kind,
+        }
+    }
+
+    /// The windows session id
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The name of the user logged on to the session, none when no user is logged on
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    /// The domain of the user logged on to the session, none when no user is logged on
+    pub fn domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
+
+    /// How the session is attached to the computer
+    pub fn kind(&self) -> SessionKind {
+        self.kind
+    }
+
+    /// Returns true for session 0, where services run instead of users
+    pub fn is_service_session(&self) -> bool {
+        self.kind == SessionKind::Service
+    }
+}
+
+/// Query information about a session from the terminal services api, read gets the buffer and its size in bytes
+fn query_session_info<R>(
+    id: u32,
+    class: u32,
+    read: impl FnOnce(winapi::um::winnt::LPWSTR, DWORD) -> R,
+) -> Option<R> {
+    let mut buffer: winapi::um::winnt::LPWSTR = std::ptr::null_mut();
+    let mut bytes: DWORD = 0;
+    let r = unsafe {
+        WTSQuerySessionInformationW(std::ptr::null_mut(), id, class, &mut buffer, &mut bytes)
+    };
+    if r == 0 || buffer.is_null() {
+        return None;
+    }
+    let value = read(buffer, bytes);
+    unsafe { WTSFreeMemory(buffer as winapi::shared::minwindef::LPVOID) };
+    Some(value)
+}
+
+/// Query a string about a session from the terminal services api, none when it is empty
+fn query_session_string(id: u32, class: u32) -> Option<String> {
+    query_session_info(id, class, |buffer, _| unsafe { from_utf16_ptr(buffer) })
+        .filter(|v| !v.is_empty())
+}
 
 /// Converts a utf8 string into a utf-16 string for windows
 pub fn get_utf16(value: &str) -> Vec<u16> {
@@ -79,7 +450,17 @@ pub fn get_utf16(value: &str) -> Vec<u16> {
         .collect()
 }
 
-/// Convert the windows style arguments to a vec of string
+/// Converts a list of strings into the double null terminated utf-16 multi-string used by windows
+pub fn get_multi_utf16(values: &[String]) -> Vec<u16> {
+    let mut block: Vec<u16> = Vec::new();
+    for v in values {
+        block.extend(get_utf16(v));
+    }
+    block.push(0);
+    block
+}
+
+/// Convert the windows style arguments to a vec of string, a null argv or null entries are skipped
 /// # Safety
 ///
 /// argv must be valid - typically straight from windows
@@ -88,20 +469,57 @@ pub unsafe fn convert_args(
     argv: *mut winapi::um::winnt::LPWSTR,
 ) -> Vec<String> {
     let mut args = Vec::new();
+    if argv.is_null() {
+        return args;
+    }
     for i in 0..argc {
         let s = *argv.add(i as usize);
+        if s.is_null() {
+            continue;
+        }
         let widestr = widestring::WideCString::from_ptr_str(s);
         args.push(widestr.to_string_lossy());
     }
     args
 }
 
-/// Converts an optional utf8 string into an optional utf-16 string for windows
-pub fn get_optional_utf16(value: Option<&str>) -> winapi::um::winnt::LPCWSTR {
-    if let Some(s) = value {
-        get_utf16(s).as_ptr()
-    } else {
-        std::ptr::null_mut()
+/// Quote a single argument so that CommandLineToArgvW parses it back to the original string
+fn escape_exec_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.chars().any(|c| matches!(c, ' ' | '\t' | '\n' | '"')) {
+        return arg.to_string();
+    }
+    let mut s = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                s.extend(std::iter::repeat('\\').take(backslashes * 2 + 1));
+                s.push('"');
+                backslashes = 0;
+            }
+            c => {
+                s.extend(std::iter::repeat('\\').take(backslashes));
+                s.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    s.extend(std::iter::repeat('\\').take(backslashes * 2));
+    s.push('"');
+    s
+}
+
+/// Converts an optional utf8 string into an optional utf-16 string for windows, the caller keeps the buffer alive while windows uses it
+pub fn get_optional_utf16(value: Option<&str>) -> Option<Vec<u16>> {
+    value.map(get_utf16)
+}
+
+/// The pointer to an optional utf-16 string, null when there is no string
+fn optional_utf16_ptr(value: &Option<Vec<u16>>) -> winapi::um::winnt::LPCWSTR {
+    match value {
+        Some(v) => v.as_ptr(),
+        None => std::ptr::null(),
     }
 }
 
@@ -118,6 +536,9 @@ impl ServiceHandle {
     }
 }
 
+// The service control manager handles can be used from any thread
+unsafe impl Send for ServiceHandle {}
+
 impl Drop for ServiceHandle {
     fn drop(&mut self) {
         if !self.handle.is_null() {
@@ -126,12 +547,205 @@ impl Drop for ServiceHandle {
     }
 }
 
+/// How start and stop wait for the service to change state.
+/// The state is checked after the initial interval, and the interval doubles after each check up to the maximum interval.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PollConfig {
+    /// The time to wait before the first check of the state
+    pub initial_interval: std::time::Duration,
+    /// The longest time to wait between two checks of the state
+    pub max_interval: std::time::Duration,
+    /// The overall time to wait for the state to change, 30 seconds by default. None waits forever
+    pub timeout: Option<std::time::Duration>,
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        Self {
+            initial_interval: std::time::Duration::from_millis(10),
+            max_interval: std::time::Duration::from_secs(1),
+            timeout: Some(DEFAULT_TIMEOUT),
+        }
+    }
+}
+
+/// Poll the state of the service while pending returns true for it, backing off as set by poll.
+/// Returns the last state, or None if the timeout elapses first.
+fn poll_state(
+    service: &ServiceHandle,
+    poll: &PollConfig,
+    pending: impl Fn(DWORD) -> bool,
+) -> Result<Option<DWORD>, ServiceError> {
+    let start = std::time::Instant::now();
+    let mut interval = poll.initial_interval;
+    let mut service_status: winapi::um::winsvc::SERVICE_STATUS =
+        winapi::um::winsvc::SERVICE_STATUS {
+            dwServiceType: winapi::um::winnt::SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: winapi::um::winsvc::SERVICE_STOPPED,
+            dwControlsAccepted: 0,
+            dwWin32ExitCode: 0,
+            dwServiceSpecificExitCode: 0,
+            dwCheckPoint: 0,
+            dwWaitHint: 0,
+        };
+    loop {
+        if unsafe { QueryServiceStatus(service.get_handle(), &mut service_status) } == 0 {
+            return Err(ServiceError::last());
+        }
+        if !pending(service_status.dwCurrentState) {
+            return Ok(Some(service_status.dwCurrentState));
+        }
+        let mut sleep = interval;
+        if let Some(timeout) = poll.timeout {
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Ok(None);
+            }
+            sleep = sleep.min(timeout - elapsed);
+        }
+        std::thread::sleep(sleep);
+        interval = (interval * 2).min(poll.max_interval);
+    }
+}
+
+/// Stop the service behind the handle, which needs stop and query status access
+fn stop_handle(
+    service: &ServiceHandle,
+    poll: &PollConfig,
+) -> Result<std::time::Duration, ServiceError> {
+    let mut service_status: winapi::um::winsvc::SERVICE_STATUS =
+        winapi::um::winsvc::SERVICE_STATUS {
+            dwServiceType: winapi::um::winnt::SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: winapi::um::winsvc::SERVICE_STOPPED,
+            dwControlsAccepted: 0,
+            dwWin32ExitCode: 0,
+            dwServiceSpecificExitCode: 0,
+            dwCheckPoint: 0,
+            dwWaitHint: 0,
+        };
+    let start = std::time::Instant::now();
+    if unsafe {
+        winapi::um::winsvc::ControlService(
+            service.get_handle(),
+            winapi::um::winsvc::SERVICE_CONTROL_STOP,
+            &mut service_status,
+        )
+    } == 0
+    {
+        let e = ServiceError::last();
+        if e.code() == winapi::shared::winerror::ERROR_SERVICE_NOT_ACTIVE {
+            return Ok(start.elapsed());
+        }
+        return Err(e);
+    }
+    let pending = |state| state == winapi::um::winsvc::SERVICE_STOP_PENDING;
+    match poll_state(service, poll, pending)? {
+        Some(_) => Ok(start.elapsed()),
+        None => Err(ServiceError::StopTimeout),
+    }
+}
+
+/// Start the service behind the handle, which needs start and query status access
+fn start_handle(
+    service: &ServiceHandle,
+    args: &[String],
+    poll: &PollConfig,
+) -> Result<std::time::Duration, StartServiceError> {
+    let args: Vec<Vec<u16>> = args.iter().map(|a| get_utf16(a)).collect();
+    let mut arg_ptrs: Vec<winapi::um::winnt::LPCWSTR> = args.iter().map(|a| a.as_ptr()).collect();
+    let p_args = if arg_ptrs.is_empty() {
+        std::ptr::null_mut()
+    } else {
+        arg_ptrs.as_mut_ptr()
+    };
+    let start = std::time::Instant::now();
+    let r = unsafe { StartServiceW(service.handle, arg_ptrs.len() as DWORD, p_args) };
+    if r == 0 {
+        let e = ServiceError::last();
+        // A service that is already running, or still starting, is waited on below
+        if e != ServiceError::ServiceAlreadyRunning {
+            return Err(StartServiceError::WindowsError(e));
+        }
+    }
+    let state = poll_state(service, poll, |state| state == SERVICE_START_PENDING)
+        .map_err(StartServiceError::WindowsError)?
+        .ok_or(StartServiceError::StartTimeout)?;
+    if state != SERVICE_RUNNING {
+        log::error!("Failed to start service {}", state);
+        Err(StartServiceError::FailedToStart(state))
+    } else {
+        Ok(start.elapsed())
+    }
+}
+
+/// Query the status of the service behind the handle, which needs query status access
+fn query_status(service: &ServiceHandle) -> Result<crate::ServiceStatus, ServiceError> {
+    let mut service_status: winapi::um::winsvc::SERVICE_STATUS =
+        winapi::um::winsvc::SERVICE_STATUS {
+            dwServiceType: winapi::um::winnt::SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: winapi::um::winsvc::SERVICE_STOPPED,
+            dwControlsAccepted: 0,
+            dwWin32ExitCode: 0,
+            dwServiceSpecificExitCode: 0,
+            dwCheckPoint: 0,
+            dwWaitHint: 0,
+        };
+    if unsafe { QueryServiceStatus(service.get_handle(), &mut service_status) } == 0 {
+        return Err(ServiceError::last());
+    }
+    Ok(status_from_state(service_status.dwCurrentState))
+}
+
+/// Convert the current state of a windows service status into the status of the service
+fn status_from_state(state: DWORD) -> crate::ServiceStatus {
+    match state {
+        SERVICE_RUNNING => crate::ServiceStatus::Running,
+        SERVICE_START_PENDING | winapi::um::winsvc::SERVICE_CONTINUE_PENDING => {
+            crate::ServiceStatus::StartPending
+        }
+        winapi::um::winsvc::SERVICE_STOP_PENDING => crate::ServiceStatus::StopPending,
+        winapi::um::winsvc::SERVICE_PAUSED | winapi::um::winsvc::SERVICE_PAUSE_PENDING => {
+            crate::ServiceStatus::Paused
+        }
+        _ => crate::ServiceStatus::Stopped,
+    }
+}
+
+/// Write a value into a registry key below HKEY_LOCAL_MACHINE, the key is created when it does not exist
+fn set_local_machine_value(
+    subkey: &str,
+    value: &str,
+    kind: DWORD,
+    data: &[u16],
+) -> Result<(), ServiceError> {
+    let subkey = get_utf16(subkey);
+    let value_name = get_utf16(value);
+    let e = unsafe {
+        winapi::um::winreg::RegSetKeyValueW(
+            winapi::um::winreg::HKEY_LOCAL_MACHINE,
+            subkey.as_ptr(),
+            value_name.as_ptr(),
+            kind,
+            data.as_ptr() as *const winapi::ctypes::c_void,
+            std::mem::size_of_val(data) as DWORD,
+        )
+    };
+    if e != 0 {
+        Err(ServiceError::from(e as DWORD))
+    } else {
+        Ok(())
+    }
+}
+
 /// Represents a service controller manager
 pub struct ServiceController {
     /// The actual handle
     handle: SC_HANDLE,
 }
 
+// The service control manager handles can be used from any thread
+unsafe impl Send for ServiceController {}
+
 impl ServiceController {
     /// Retrieve the handle
     pub fn get_handle(&self) -> SC_HANDLE {
@@ -160,6 +774,8 @@ impl ServiceController {
 }
 
 /// The configuration for constructing a Service
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct ServiceConfig {
     /// The arguments for the service
     arguments: Vec<String>,
@@ -186,195 +802,1609 @@ pub struct ServiceConfig {
     /// Load ordering groups that the service should belong to
     pub load_order_group: Option<String>,
     /// Other services that must start before this one
-    pub dependencies: Option<String>,
+    pub dependencies: Vec<String>,
+    /// The environment variables provided to the service
+    pub environment: Vec<(String, String)>,
+    /// Register the binary of the service as the message file of its event log source, so that Event Viewer shows the log entries without the missing description text.
+    /// When false, the program calling create is registered as the message file, as the eventlog crate does
+    pub event_message_file: bool,
+    /// The path to the configuration data for the service, found by the service with [Service::config_path]. When empty, [Service::default_config_path] is used
+    pub config_path: PathBuf,
+    /// Allow creating the service before its binary exists, for staged installs where the binary is put in place afterwards
+    pub allow_missing_binary: bool,
+    /// What the service control manager does when the service fails
+    pub failure_actions: Option<FailureActions>,
+    /// Start the service shortly after the other auto start services, only used when start_type is SERVICE_AUTO_START
+    pub delayed_auto_start: bool,
+    /// The kind of security identifier the service control manager adds to the service process
+    pub sid_type: SidType,
+    /// How long windows waits for the service to stop when it accepts preshutdown notifications, none leaves the windows default
+    pub preshutdown_timeout: Option<std::time::Duration>,
+    /// Events that start or stop the service, a service with triggers is always created with SERVICE_DEMAND_START
+    pub triggers: Vec<ServiceTrigger>,
+    /// Create a per user template service with SERVICE_USER_OWN_PROCESS, windows runs an instance of it in the session of each user that logs on.
+    /// The instances run as the logged on user, so the username and password are ignored
+    pub per_user: bool,
 }
 
-impl ServiceConfig {
-    /// Build a new service config with reasonable defaults.
-    /// # Arguments
-    /// * arguments - The list of arguments for the service
-    /// * description - The description of the service
-    /// * binary - The path to the binary that runs the service
-    /// * config_path - The configuration path for the service
-    /// * username - The username the service runs as
-    /// * display - The display name of the service
-    /// * user_password - The password for the user to run the service as
-    pub fn new(
-        arguments: Vec<String>,
-        description: String,
-        binary: PathBuf,
-        username: Option<String>,
-    ) -> Self {
-        Self {
-            arguments,
-            description,
-            binary,
-            username,
-            display: String::new(),
-            user_password: None,
-            desired_access: winapi::um::winsvc::SERVICE_ALL_ACCESS,
-            service_type: winapi::um::winnt::SERVICE_WIN32_OWN_PROCESS,
-            start_type: winapi::um::winnt::SERVICE_AUTO_START,
-            error_control: winapi::um::winnt::SERVICE_ERROR_NORMAL,
-            tag_id: 0,
-            load_order_group: None,
-            dependencies: None,
+/// The kind of per service security identifier given to the service process
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SidType {
+    /// The service has no service specific identifier
+    None,
+    /// The service identifier is added to the process token
+    Unrestricted,
+    /// The service identifier is added to the process token as a restricted identifier, limiting the process to resources granted to it
+    Restricted,
+}
+
+impl Default for SidType {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl SidType {
+    /// Convert to the windows sid type
+    fn sid_type(&self) -> DWORD {
+        match self {
+            SidType::None => winapi::um::winsvc::SERVICE_SID_TYPE_NONE,
+            SidType::Unrestricted => winapi::um::winsvc::SERVICE_SID_TYPE_UNRESTRICTED,
+            SidType::Restricted => winapi::um::winsvc::SERVICE_SID_TYPE_RESTRICTED,
         }
     }
 }
 
-/// Represents a service on the system
-pub struct Service {
-    /// The name of the service, as known by the operating system
-    name: String,
+/// An action the service control manager takes when the service fails
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FailureAction {
+    /// Do nothing
+    None,
+    /// Restart the service
+    RestartService,
+    /// Run the command from [FailureActions]
+    RunCommand,
+    /// Reboot the computer, this requires the shutdown privilege when creating the service
+    Reboot,
 }
 
-impl Service {
-    /// Construct a new self
-    pub fn new(name: String) -> Self {
-        Self { name }
+impl FailureAction {
+    /// Convert to the windows action type
+    fn action_type(&self) -> winapi::um::winsvc::SC_ACTION_TYPE {
+        match self {
+            FailureAction::None => winapi::um::winsvc::SC_ACTION_NONE,
+            FailureAction::RestartService => winapi::um::winsvc::SC_ACTION_RESTART,
+            FailureAction::RunCommand => winapi::um::winsvc::SC_ACTION_RUN_COMMAND,
+            FailureAction::Reboot => winapi::um::winsvc::SC_ACTION_REBOOT,
+        }
     }
+}
 
-    /// Initialize a new log instance
-    pub fn new_log(&self, level: super::LogLevel) {
-        eventlog::init(&format!("{} Log", self.name), level.level()).unwrap();
+/// The recovery settings for a service, as shown on the recovery tab of the services console
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct FailureActions {
+    /// How long without a failure before the failure count is reset to zero
+    pub reset_period: std::time::Duration,
+    /// The actions for the first, second, and later failures, with the delay before each action
+    pub actions: Vec<(FailureAction, std::time::Duration)>,
+    /// The command line run for [FailureAction::RunCommand]
+    pub command: Option<String>,
+    /// The message broadcast to users before a [FailureAction::Reboot]
+    pub reboot_message: Option<String>,
+}
+
+/// Apply the failure actions to an open service
+fn set_failure_actions(
+    service: winapi::um::winsvc::SC_HANDLE,
+    fa: &FailureActions,
+) -> Result<(), ServiceError> {
+    let mut actions: Vec<winapi::um::winsvc::SC_ACTION> = fa
+        .actions
+        .iter()
+        .map(|(a, delay)| winapi::um::winsvc::SC_ACTION {
+            Type: a.action_type(),
+            Delay: delay.as_millis() as DWORD,
+        })
+        .collect();
+    let mut command = fa.command.as_deref().map(get_utf16);
+    let mut reboot = fa.reboot_message.as_deref().map(get_utf16);
+    let mut sfa = winapi::um::winsvc::SERVICE_FAILURE_ACTIONSW {
+        dwResetPeriod: fa.reset_period.as_secs() as DWORD,
+        lpRebootMsg: reboot
+            .as_mut()
+            .map(|r| r.as_mut_ptr())
+            .unwrap_or(std::ptr::null_mut()),
+        lpCommand: command
+            .as_mut()
+            .map(|c| c.as_mut_ptr())
+            .unwrap_or(std::ptr::null_mut()),
+        cActions: actions.len() as DWORD,
+        lpsaActions: actions.as_mut_ptr(),
+    };
+    let p_sfa = &mut sfa as *mut _ as *mut winapi::ctypes::c_void;
+    let r = unsafe {
+        winapi::um::winsvc::ChangeServiceConfig2W(
+            service,
+            winapi::um::winsvc::SERVICE_CONFIG_FAILURE_ACTIONS,
+            p_sfa,
+        )
+    };
+    if r == 0 {
+        Err(ServiceError::last())
+    } else {
+        Ok(())
     }
+}
 
-    /// Does the service already exist?
-    pub fn exists(&self) -> bool {
-        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_ALL_ACCESS)
-            .unwrap_or_else(|e| panic!("Unable to get service controller {}", e)); //TODO REMOVE RIGHTS NOT REQUIRED
-        let service =
-            service_manager.open_service(&self.name, winapi::um::winsvc::SERVICE_ALL_ACCESS);
-        service.is_ok()
+/// The optional configuration level for the triggers of a service, missing from winapi
+const SERVICE_CONFIG_TRIGGER_INFO: DWORD = 8;
+/// The trigger type for the arrival of a device interface
+const SERVICE_TRIGGER_TYPE_DEVICE_INTERFACE_ARRIVAL: DWORD = 1;
+/// The trigger type for ip address changes
+const SERVICE_TRIGGER_TYPE_IP_ADDRESS_AVAILABILITY: DWORD = 2;
+/// The trigger type for domain membership changes
+const SERVICE_TRIGGER_TYPE_DOMAIN_JOIN: DWORD = 3;
+/// The trigger type for group policy changes
+const SERVICE_TRIGGER_TYPE_GROUP_POLICY: DWORD = 5;
+/// The trigger action that starts the service
+const SERVICE_TRIGGER_ACTION_SERVICE_START: DWORD = 1;
+/// The trigger action that stops the service
+const SERVICE_TRIGGER_ACTION_SERVICE_STOP: DWORD = 2;
+/// The trigger data is a utf-16 string
+const SERVICE_TRIGGER_DATA_TYPE_STRING: DWORD = 2;
+/// NETWORK_MANAGER_FIRST_IP_ADDRESS_ARRIVAL_GUID
+const FIRST_IP_ADDRESS_ARRIVAL: u128 = 0x4f27f2de_14e2_430b_a549_7cd48cbc8245;
+/// NETWORK_MANAGER_LAST_IP_ADDRESS_REMOVAL_GUID
+const LAST_IP_ADDRESS_REMOVAL: u128 = 0xcc4ba62a_162e_4648_847a_b6bdf993e335;
+/// DOMAIN_JOIN_GUID
+const DOMAIN_JOIN: u128 = 0x1ce20aba_9851_4421_9430_1ddeb766e809;
+/// DOMAIN_LEAVE_GUID
+const DOMAIN_LEAVE: u128 = 0xddaf516e_58c2_4866_9574_c3b615d42ea1;
+/// MACHINE_POLICY_PRESENT_GUID
+const MACHINE_POLICY_PRESENT: u128 = 0x659fcae6_5bdb_4da9_b1ff_ca2a178d46e0;
+/// USER_POLICY_PRESENT_GUID
+const USER_POLICY_PRESENT: u128 = 0x54fb46c8_f089_464c_b1fd_59d1b62c3b50;
+
+/// An event that makes the service control manager start or stop the service
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TriggerEvent {
+    /// A device of the interface class arrives or is present at boot. The class is the interface guid written as a number,
+    /// such as 0x4d1e55b2_f16f_11cf_88cb_001111000030 for hid devices. Hardware ids limit the trigger to matching devices, empty matches every device
+    DeviceArrival {
+        /// The device interface class guid
+        interface_class: u128,
+        /// The hardware or compatible ids of the devices
+        hardware_ids: Vec<String>,
+    },
+    /// The first ip address becomes available
+    IpAddressArrival,
+    /// The last ip address is removed
+    IpAddressRemoval,
+    /// The computer joins a domain
+    DomainJoin,
+    /// The computer leaves a domain
+    DomainLeave,
+    /// The machine group policy changes or is present at boot
+    MachinePolicy,
+    /// The user group policy changes or is present when a user logs on
+    UserPolicy,
+}
+
+impl TriggerEvent {
+    /// The windows trigger type and subtype guid of the event
+    fn windows_type(&self) -> (DWORD, u128) {
+        match self {
+            TriggerEvent::DeviceArrival {
+                interface_class, ..
+            } => (
+                SERVICE_TRIGGER_TYPE_DEVICE_INTERFACE_ARRIVAL,
+                *interface_class,
+            ),
+            TriggerEvent::IpAddressArrival => (
+                SERVICE_TRIGGER_TYPE_IP_ADDRESS_AVAILABILITY,
+                FIRST_IP_ADDRESS_ARRIVAL,
+            ),
+            TriggerEvent::IpAddressRemoval => (
+                SERVICE_TRIGGER_TYPE_IP_ADDRESS_AVAILABILITY,
+                LAST_IP_ADDRESS_REMOVAL,
+            ),
+            TriggerEvent::DomainJoin => (SERVICE_TRIGGER_TYPE_DOMAIN_JOIN, DOMAIN_JOIN),
+            TriggerEvent::DomainLeave => (SERVICE_TRIGGER_TYPE_DOMAIN_JOIN, DOMAIN_LEAVE),
+            TriggerEvent::MachinePolicy => {
+                (SERVICE_TRIGGER_TYPE_GROUP_POLICY, MACHINE_POLICY_PRESENT)
+            }
+            TriggerEvent::UserPolicy => (SERVICE_TRIGGER_TYPE_GROUP_POLICY, USER_POLICY_PRESENT),
+        }
     }
 
-    /// Stop the service
-    pub fn stop(&mut self) -> Result<(), DWORD> {
-        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_ALL_ACCESS)?; //TODO REMOVE RIGHTS NOT REQUIRED
-        let service = service_manager
-            .open_service(&self.name, winapi::um::winsvc::SERVICE_ALL_ACCESS)
-            .unwrap();
-        let mut service_status: winapi::um::winsvc::SERVICE_STATUS =
-            winapi::um::winsvc::SERVICE_STATUS {
-                dwServiceType: winapi::um::winnt::SERVICE_WIN32_OWN_PROCESS,
-                dwCurrentState: winapi::um::winsvc::SERVICE_STOPPED,
-                dwControlsAccepted: 0,
-                dwWin32ExitCode: 0,
-                dwServiceSpecificExitCode: 0,
-                dwCheckPoint: 0,
-                dwWaitHint: 0,
-            };
-        if unsafe {
-            winapi::um::winsvc::ControlService(
-                service.get_handle(),
-                winapi::um::winsvc::SERVICE_CONTROL_STOP,
-                &mut service_status,
-            )
-        } != 0
-        {
-            while unsafe {
-                winapi::um::winsvc::QueryServiceStatus(service.get_handle(), &mut service_status)
-            } != 0
-            {
-                if service_status.dwCurrentState != winapi::um::winsvc::SERVICE_STOP_PENDING {
-                    break;
+    /// Convert a windows trigger type and subtype guid back into an event, none for triggers that are not covered
+    fn from_windows(trigger_type: DWORD, subtype: u128, hardware_ids: Vec<String>) -> Option<Self> {
+        Some(match (trigger_type, subtype) {
+            (SERVICE_TRIGGER_TYPE_DEVICE_INTERFACE_ARRIVAL, interface_class) => {
+                TriggerEvent::DeviceArrival {
+                    interface_class,
+                    hardware_ids,
                 }
-                std::thread::sleep(std::time::Duration::from_millis(250));
             }
+            (SERVICE_TRIGGER_TYPE_IP_ADDRESS_AVAILABILITY, FIRST_IP_ADDRESS_ARRIVAL) => {
+                TriggerEvent::IpAddressArrival
+            }
+            (SERVICE_TRIGGER_TYPE_IP_ADDRESS_AVAILABILITY, LAST_IP_ADDRESS_REMOVAL) => {
+                TriggerEvent::IpAddressRemoval
+            }
+            (SERVICE_TRIGGER_TYPE_DOMAIN_JOIN, DOMAIN_JOIN) => TriggerEvent::DomainJoin,
+            (SERVICE_TRIGGER_TYPE_DOMAIN_JOIN, DOMAIN_LEAVE) => TriggerEvent::DomainLeave,
+            (SERVICE_TRIGGER_TYPE_GROUP_POLICY, MACHINE_POLICY_PRESENT) => {
+                TriggerEvent::MachinePolicy
+            }
+            (SERVICE_TRIGGER_TYPE_GROUP_POLICY, USER_POLICY_PRESENT) => TriggerEvent::UserPolicy,
+            _ => return None,
+        })
+    }
+}
+
+/// What the service control manager does when a trigger event occurs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TriggerAction {
+    /// Start the service
+    Start,
+    /// Stop the service
+    Stop,
+}
+
+/// Starts or stops the service when an event occurs
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServiceTrigger {
+    /// The event that fires the trigger
+    pub event: TriggerEvent,
+    /// What happens to the service when the event occurs
+    pub action: TriggerAction,
+}
+
+impl ServiceTrigger {
+    /// A trigger that starts the service on the event
+    pub fn start(event: TriggerEvent) -> Self {
+        Self {
+            event,
+            action: TriggerAction::Start,
         }
-        Ok(())
     }
 
-    /// Start the service
-    pub fn start(&mut self) -> Result<(), StartServiceError> {
-        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_ALL_ACCESS)
-            .map_err(StartServiceError::WindowsError)?; //TODO REMOVE RIGHTS NOT REQUIRED
-        let service = service_manager
-            .open_service(&self.name, winapi::um::winsvc::SERVICE_ALL_ACCESS)
-            .unwrap();
-        let mut service_status: winapi::um::winsvc::SERVICE_STATUS =
-            winapi::um::winsvc::SERVICE_STATUS {
-                dwServiceType: winapi::um::winnt::SERVICE_WIN32_OWN_PROCESS,
-                dwCurrentState: winapi::um::winsvc::SERVICE_STOPPED,
-                dwControlsAccepted: 0,
-                dwWin32ExitCode: 0,
-                dwServiceSpecificExitCode: 0,
-                dwCheckPoint: 0,
-                dwWaitHint: 0,
-            };
-        if unsafe { StartServiceW(service.handle, 0, std::ptr::null_mut()) } != 0 {
-            while unsafe { QueryServiceStatus(service.handle, &mut service_status) } != 0 {
-                if service_status.dwCurrentState != SERVICE_START_PENDING {
-                    break;
-                }
-                std::thread::sleep(std::time::Duration::from_millis(250));
-            }
-        } else {
-            todo!();
+    /// A trigger that stops the service on the event
+    pub fn stop(event: TriggerEvent) -> Self {
+        Self {
+            event,
+            action: TriggerAction::Stop,
+        }
+    }
+}
+
+/// SERVICE_TRIGGER_SPECIFIC_DATA_ITEM, missing from winapi
+#[repr(C)]
+struct TriggerDataItem {
+    /// The type of the data
+    data_type: DWORD,
+    /// The size of the data in bytes
+    size: DWORD,
+    /// The data
+    data: *mut u8,
+}
+
+/// SERVICE_TRIGGER, missing from winapi
+#[repr(C)]
+struct Trigger {
+    /// The trigger type
+    trigger_type: DWORD,
+    /// The action taken when the trigger fires
+    action: DWORD,
+    /// The subtype of the trigger
+    subtype: *mut winapi::shared::guiddef::GUID,
+    /// The number of data items
+    data_item_count: DWORD,
+    /// The data items that further limit the trigger
+    data_items: *mut TriggerDataItem,
+}
+
+/// SERVICE_TRIGGER_INFO, missing from winapi
+#[repr(C)]
+struct TriggerInfo {
+    /// The number of triggers
+    count: DWORD,
+    /// The triggers
+    triggers: *mut Trigger,
+    /// Reserved, must be null
+    reserved: *mut u8,
+}
+
+/// Convert a guid written as a number into a windows guid
+fn to_guid(value: u128) -> winapi::shared::guiddef::GUID {
+    winapi::shared::guiddef::GUID {
+        Data1: (value >> 96) as u32,
+        Data2: (value >> 80) as u16,
+        Data3: (value >> 64) as u16,
+        Data4: (value as u64).to_be_bytes(),
+    }
+}
+
+/// Convert a windows guid into a number
+fn from_guid(guid: &winapi::shared::guiddef::GUID) -> u128 {
+    ((guid.Data1 as u128) << 96)
+        | ((guid.Data2 as u128) << 80)
+        | ((guid.Data3 as u128) << 64)
+        | u64::from_be_bytes(guid.Data4) as u128
+}
+
+/// Apply the triggers to an open service, an empty list removes all triggers
+fn set_triggers(
+    service: winapi::um::winsvc::SC_HANDLE,
+    triggers: &[ServiceTrigger],
+) -> Result<(), ServiceError> {
+    let mut guids: Vec<winapi::shared::guiddef::GUID> = triggers
+        .iter()
+        .map(|t| to_guid(t.event.windows_type().1))
+        .collect();
+    let mut strings: Vec<Vec<Vec<u16>>> = triggers
+        .iter()
+        .map(|t| match &t.event {
+            TriggerEvent::DeviceArrival { hardware_ids, .. } => {
+                hardware_ids.iter().map(|id| get_utf16(id)).collect()
+            }
+            _ => Vec::new(),
+        })
+        .collect();
+    let mut items: Vec<Vec<TriggerDataItem>> = strings
+        .iter_mut()
+        .map(|ids| {
+            ids.iter_mut()
+                .map(|id| TriggerDataItem {
+                    data_type: SERVICE_TRIGGER_DATA_TYPE_STRING,
+                    size: (id.len() * std::mem::size_of::<u16>()) as DWORD,
+                    data: id.as_mut_ptr() as *mut u8,
+                })
+                .collect()
+        })
+        .collect();
+    let mut windows_triggers: Vec<Trigger> = triggers
+        .iter()
+        .zip(guids.iter_mut())
+        .zip(items.iter_mut())
+        .map(|((t, guid), items)| Trigger {
+            trigger_type: t.event.windows_type().0,
+            action: match t.action {
+                TriggerAction::Start => SERVICE_TRIGGER_ACTION_SERVICE_START,
+                TriggerAction::Stop => SERVICE_TRIGGER_ACTION_SERVICE_STOP,
+            },
+            subtype: guid,
+            data_item_count: items.len() as DWORD,
+            data_items: if items.is_empty() {
+                std::ptr::null_mut()
+            } else {
+                items.as_mut_ptr()
+            },
+        })
+        .collect();
+    let mut info = TriggerInfo {
+        count: windows_triggers.len() as DWORD,
+        triggers: if windows_triggers.is_empty() {
+            std::ptr::null_mut()
+        } else {
+            windows_triggers.as_mut_ptr()
+        },
+        reserved: std::ptr::null_mut(),
+    };
+    let p_info = &mut info as *mut _ as *mut winapi::ctypes::c_void;
+    let r = unsafe {
+        winapi::um::winsvc::ChangeServiceConfig2W(service, SERVICE_CONFIG_TRIGGER_INFO, p_info)
+    };
+    if r == 0 {
+        Err(ServiceError::last())
+    } else {
+        Ok(())
+    }
+}
+
+/// Read the triggers of an open service, triggers that are not covered by [TriggerEvent] are left out
+fn query_triggers(
+    service: winapi::um::winsvc::SC_HANDLE,
+) -> Result<Vec<ServiceTrigger>, ServiceError> {
+    let buf = query_config2(service, SERVICE_CONFIG_TRIGGER_INFO)?;
+    let info = unsafe { &*(buf.as_ptr() as *const TriggerInfo) };
+    if info.count == 0 || info.triggers.is_null() {
+        return Ok(Vec::new());
+    }
+    let triggers = unsafe { std::slice::from_raw_parts(info.triggers, info.count as usize) };
+    let mut result = Vec::new();
+    for t in triggers {
+        let subtype = if t.subtype.is_null() {
+            0
+        } else {
+            from_guid(unsafe { &*t.subtype })
+        };
+        let mut hardware_ids = Vec::new();
+        if t.data_item_count > 0 && !t.data_items.is_null() {
+            let items =
+                unsafe { std::slice::from_raw_parts(t.data_items, t.data_item_count as usize) };
+            for item in items {
+                if item.data_type != SERVICE_TRIGGER_DATA_TYPE_STRING || item.data.is_null() {
+                    continue;
+                }
+                let len = item.size as usize / std::mem::size_of::<u16>();
+                let data = unsafe { std::slice::from_raw_parts(item.data as *const u16, len) };
+                let data = String::from_utf16_lossy(data);
+                hardware_ids.extend(
+                    data.split('\0')
+                        .filter(|id| !id.is_empty())
+                        .map(String::from),
+                );
+            }
+        }
+        let action = match t.action {
+            SERVICE_TRIGGER_ACTION_SERVICE_STOP => TriggerAction::Stop,
+            _ => TriggerAction::Start,
+        };
+        if let Some(event) = TriggerEvent::from_windows(t.trigger_type, subtype, hardware_ids) {
+            result.push(ServiceTrigger { event, action });
+        }
+    }
+    Ok(result)
+}
+
+/// Convert a null terminated utf-16 string from windows, a null pointer is an empty string
+/// # Safety
+///
+/// p must be null or point to a null terminated string
+unsafe fn from_utf16_ptr(p: *const u16) -> String {
+    if p.is_null() {
+        String::new()
+    } else {
+        widestring::WideCString::from_ptr_str(p).to_string_lossy()
+    }
+}
+
+/// Convert a double null terminated utf-16 multi-string from windows into a list of strings
+/// # Safety
+///
+/// p must be null or point to a double null terminated multi-string
+unsafe fn from_multi_utf16(mut p: *const u16) -> Vec<String> {
+    let mut values = Vec::new();
+    if p.is_null() {
+        return values;
+    }
+    while *p != 0 {
+        let widestr = widestring::WideCStr::from_ptr_str(p);
+        p = p.add(widestr.len() + 1);
+        values.push(widestr.to_string_lossy());
+    }
+    values
+}
+
+/// Read one of the optional configuration structures of an open service, the buffer is u64 to keep the structure aligned
+fn query_config2(
+    service: winapi::um::winsvc::SC_HANDLE,
+    level: DWORD,
+) -> Result<Vec<u64>, ServiceError> {
+    let mut needed: DWORD = 0;
+    unsafe {
+        winapi::um::winsvc::QueryServiceConfig2W(
+            service,
+            level,
+            std::ptr::null_mut(),
+            0,
+            &mut needed,
+        )
+    };
+    let mut buf: Vec<u64> = vec![0; (needed as usize + 7) / 8 + 1];
+    let r = unsafe {
+        winapi::um::winsvc::QueryServiceConfig2W(
+            service,
+            level,
+            buf.as_mut_ptr() as *mut u8,
+            (buf.len() * 8) as DWORD,
+            &mut needed,
+        )
+    };
+    if r == 0 {
+        Err(ServiceError::last())
+    } else {
+        Ok(buf)
+    }
+}
+
+/// Apply one of the optional configuration structures that consist of a single DWORD, such as SERVICE_DELAYED_AUTO_START_INFO
+fn set_config2_dword(
+    service: winapi::um::winsvc::SC_HANDLE,
+    level: DWORD,
+    mut value: DWORD,
+) -> Result<(), ServiceError> {
+    let p_value = &mut value as *mut _ as *mut winapi::ctypes::c_void;
+    let r = unsafe { winapi::um::winsvc::ChangeServiceConfig2W(service, level, p_value) };
+    if r == 0 {
+        Err(ServiceError::last())
+    } else {
+        Ok(())
+    }
+}
+
+/// Apply the optional configuration of the service config to an open service
+fn set_optional_config(
+    service: winapi::um::winsvc::SC_HANDLE,
+    config: &ServiceConfig,
+) -> Result<(), ServiceError> {
+    if let Some(fa) = &config.failure_actions {
+        set_failure_actions(service, fa)?;
+    }
+    if config.windows_start_type() == winapi::um::winnt::SERVICE_AUTO_START {
+        set_config2_dword(
+            service,
+            winapi::um::winsvc::SERVICE_CONFIG_DELAYED_AUTO_START_INFO,
+            config.delayed_auto_start as DWORD,
+        )?;
+    }
+    set_config2_dword(
+        service,
+        winapi::um::winsvc::SERVICE_CONFIG_SERVICE_SID_INFO,
+        config.sid_type.sid_type(),
+    )?;
+    if let Some(timeout) = config.preshutdown_timeout {
+        set_config2_dword(
+            service,
+            winapi::um::winsvc::SERVICE_CONFIG_PRESHUTDOWN_INFO,
+            timeout.as_millis() as DWORD,
+        )?;
+    }
+    set_triggers(service, &config.triggers)
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self::new(Vec::new(), String::new(), PathBuf::new(), None)
+    }
+}
+
+impl ServiceConfig {
+    /// Build a new service config with reasonable defaults.
+    /// # Arguments
+    /// * arguments - The list of arguments for the service
+    /// * description - The description of the service
+    /// * binary - The path to the binary that runs the service
+    /// * config_path - The configuration path for the service
+    /// * username - The username the service runs as
+    /// * display - The display name of the service
+    /// * user_password - The password for the user to run the service as
+    pub fn new(
+        arguments: Vec<String>,
+        description: String,
+        binary: PathBuf,
+        username: Option<String>,
+    ) -> Self {
+        Self {
+            arguments,
+            description,
+            binary,
+            username,
+            display: String::new(),
+            user_password: None,
+            desired_access: winapi::um::winsvc::SERVICE_ALL_ACCESS,
+            service_type: winapi::um::winnt::SERVICE_WIN32_OWN_PROCESS,
+            start_type: winapi::um::winnt::SERVICE_AUTO_START,
+            error_control: winapi::um::winnt::SERVICE_ERROR_NORMAL,
+            tag_id: 0,
+            load_order_group: None,
+            dependencies: Vec::new(),
+            environment: Vec::new(),
+            config_path: PathBuf::new(),
+            allow_missing_binary: false,
+            event_message_file: true,
+            failure_actions: None,
+            delayed_auto_start: false,
+            sid_type: SidType::default(),
+            preshutdown_timeout: None,
+            triggers: Vec::new(),
+            per_user: false,
+        }
+    }
+
+    /// The service type given to windows, SERVICE_USER_OWN_PROCESS for a per user service
+    fn windows_service_type(&self) -> DWORD {
+        if self.per_user {
+            winapi::um::winnt::SERVICE_USER_OWN_PROCESS
+        } else {
+            self.service_type
+        }
+    }
+
+    /// The command line of the service, the quoted binary followed by the quoted arguments.
+    /// An error is returned when the binary path is not valid unicode
+    fn command_line(&self) -> Result<String, CreateServiceError> {
+        let binary = self
+            .binary
+            .to_str()
+            .ok_or_else(|| CreateServiceError::InvalidBinaryPath(self.binary.clone()))?;
+        let mut line = escape_exec_arg(binary);
+        for arg in &self.arguments {
+            line.push(' ');
+            line.push_str(&escape_exec_arg(arg));
+        }
+        Ok(line)
+    }
+
+    /// The access needed on the service to change it to this config.
+    /// Failure actions that restart the service also need start access
+    fn change_access(&self) -> DWORD {
+        let restarts = self.failure_actions.as_ref().map_or(false, |fa| {
+            fa.actions
+                .iter()
+                .any(|(a, _)| *a == FailureAction::RestartService)
+        });
+        if restarts {
+            winapi::um::winsvc::SERVICE_CHANGE_CONFIG | winapi::um::winsvc::SERVICE_START
+        } else {
+            winapi::um::winsvc::SERVICE_CHANGE_CONFIG
+        }
+    }
+
+    /// The binary as a canonical absolute path, with symlinks and `..` resolved.
+    /// An error is returned when the binary does not exist, unless allow_missing is true, then a relative path is only joined to the current directory
+    fn absolute_binary(&self, allow_missing: bool) -> Result<PathBuf, CreateServiceError> {
+        let binary = if self.binary.is_relative() {
+            std::env::current_dir()
+                .map_err(CreateServiceError::FileIoError)?
+                .join(&self.binary)
+        } else {
+            self.binary.clone()
+        };
+        match std::fs::canonicalize(&binary) {
+            Ok(canonical) if canonical.is_file() => Ok(strip_verbatim(canonical)),
+            _ if allow_missing => Ok(binary),
+            _ => Err(CreateServiceError::BinaryNotFound(binary)),
+        }
+    }
+
+    /// The arguments for the service
+    pub fn arguments(&self) -> &[String] {
+        &self.arguments
+    }
+
+    /// The description of the service
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// The path to the binary that runs the service
+    pub fn binary(&self) -> &std::path::Path {
+        &self.binary
+    }
+
+    /// The username the service runs as
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    /// The start type given to windows, demand start when the service has triggers
+    fn windows_start_type(&self) -> DWORD {
+        if self.triggers.is_empty() {
+            self.start_type
+        } else {
+            winapi::um::winnt::SERVICE_DEMAND_START
+        }
+    }
+}
+
+/// The maximum length of a service name accepted by the service control manager
+const MAX_NAME_LENGTH: usize = 256;
+
+/// Turn a verbatim path from canonicalize, such as `\\?\C:\dir`, back into the usual form that the service control manager and other programs expect
+fn strip_verbatim(path: PathBuf) -> PathBuf {
+    let Some(s) = path.to_str() else {
+        return path;
+    };
+    if let Some(unc) = s.strip_prefix(r"\\?\UNC\") {
+        PathBuf::from(format!(r"\\{}", unc))
+    } else if let Some(disk) = s.strip_prefix(r"\\?\") {
+        PathBuf::from(disk)
+    } else {
+        path
+    }
+}
+
+/// Check that a name is accepted by the service control manager
+fn validate_name(name: &str) -> Result<(), crate::InvalidServiceName> {
+    if name.is_empty() {
+        return Err(crate::InvalidServiceName::Empty);
+    }
+    if name.encode_utf16().count() > MAX_NAME_LENGTH {
+        return Err(crate::InvalidServiceName::TooLong(MAX_NAME_LENGTH));
+    }
+    if let Some(c) = name.chars().find(|c| matches!(c, '/' | '\\' | '\0')) {
+        return Err(crate::InvalidServiceName::InvalidCharacter(c));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "async")]
+/// Run a blocking operation on a copy of the service in the blocking thread pool of tokio, a panic is passed on to the caller
+async fn run_blocking<R: Send + 'static>(
+    service: &Service,
+    f: impl FnOnce(&mut Service) -> R + Send + 'static,
+) -> R {
+    let mut service = service.clone();
+    match tokio::task::spawn_blocking(move || f(&mut service)).await {
+        Ok(r) => r,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+/// An open connection to a service, from [Service::connect], so that several operations reuse the same handles.
+/// The handles are closed when this is dropped
+pub struct OpenService {
+    /// The handle of the service
+    service: ServiceHandle,
+    /// The service controller manager that the service was opened with
+    _manager: ServiceController,
+    /// How long stop waits for the service to stop, None waits forever
+    stop_timeout: Option<std::time::Duration>,
+}
+
+impl OpenService {
+    /// The handle of the service
+    pub fn handle(&self) -> &ServiceHandle {
+        &self.service
+    }
+
+    /// Start the service, starting a service that is already running does nothing and succeeds
+    pub fn start(&self) -> Result<(), StartServiceError> {
+        self.start_with_poll(&[], &PollConfig::default())
+            .map(|_| ())
+    }
+
+    /// Start the service with start parameters, waiting for it to start as set by poll. Returns how long the start took
+    pub fn start_with_poll(
+        &self,
+        args: &[String],
+        poll: &PollConfig,
+    ) -> Result<std::time::Duration, StartServiceError> {
+        start_handle(&self.service, args, poll)
+    }
+
+    /// Stop the service, stopping a service that is not running does nothing and succeeds
+    pub fn stop(&self) -> Result<(), ServiceError> {
+        let poll = PollConfig {
+            timeout: self.stop_timeout,
+            ..PollConfig::default()
+        };
+        self.stop_with_poll(&poll).map(|_| ())
+    }
+
+    /// Stop the service, waiting for it to stop as set by poll. Returns how long the stop took
+    pub fn stop_with_poll(&self, poll: &PollConfig) -> Result<std::time::Duration, ServiceError> {
+        stop_handle(&self.service, poll)
+    }
+
+    /// Query the current status of the service
+    pub fn status(&self) -> Result<crate::ServiceStatus, ServiceError> {
+        query_status(&self.service)
+    }
+}
+
+/// The states of a service that a status watch is notified about
+const NOTIFY_STATES: DWORD = winapi::um::winsvc::SERVICE_NOTIFY_STOPPED
+    | winapi::um::winsvc::SERVICE_NOTIFY_START_PENDING
+    | winapi::um::winsvc::SERVICE_NOTIFY_STOP_PENDING
+    | winapi::um::winsvc::SERVICE_NOTIFY_RUNNING
+    | winapi::um::winsvc::SERVICE_NOTIFY_CONTINUE_PENDING
+    | winapi::um::winsvc::SERVICE_NOTIFY_PAUSE_PENDING
+    | winapi::um::winsvc::SERVICE_NOTIFY_PAUSED;
+
+/// The notify flag for the current state of a windows service status
+fn notify_flag(state: DWORD) -> DWORD {
+    match state {
+        SERVICE_RUNNING => winapi::um::winsvc::SERVICE_NOTIFY_RUNNING,
+        SERVICE_START_PENDING => winapi::um::winsvc::SERVICE_NOTIFY_START_PENDING,
+        winapi::um::winsvc::SERVICE_STOP_PENDING => winapi::um::winsvc::SERVICE_NOTIFY_STOP_PENDING,
+        winapi::um::winsvc::SERVICE_CONTINUE_PENDING => {
+            winapi::um::winsvc::SERVICE_NOTIFY_CONTINUE_PENDING
+        }
+        winapi::um::winsvc::SERVICE_PAUSE_PENDING => {
+            winapi::um::winsvc::SERVICE_NOTIFY_PAUSE_PENDING
+        }
+        winapi::um::winsvc::SERVICE_PAUSED => winapi::um::winsvc::SERVICE_NOTIFY_PAUSED,
+        _ => winapi::um::winsvc::SERVICE_NOTIFY_STOPPED,
+    }
+}
+
+/// A watch on the status of a service, from [Service::watch_status]. The watch ends when it is cancelled or dropped
+pub struct StatusWatch {
+    /// Set when the watch should end
+    cancelled: Arc<std::sync::atomic::AtomicBool>,
+    /// The thread that waits for the notifications from windows
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl StatusWatch {
+    /// End the watch, the callback is not called again once this returns
+    pub fn cancel(mut self) {
+        self.end();
+    }
+
+    /// Tell the watch thread to end, and wait for it
+    fn end(&mut self) {
+        use std::os::windows::io::AsRawHandle;
+        if let Some(thread) = self.thread.take() {
+            self.cancelled
+                .store(true, std::sync::atomic::Ordering::SeqCst);
+            let handle = thread.as_raw_handle() as winapi::um::winnt::HANDLE;
+            // wake the thread from its alertable wait, so that it sees the cancel
+            unsafe { winapi::um::processthreadsapi::QueueUserAPC(Some(wake_watch), handle, 0) };
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for StatusWatch {
+    fn drop(&mut self) {
+        self.end();
+    }
+}
+
+/// Does nothing, queued to wake a watch thread from its alertable wait
+unsafe extern "system" fn wake_watch(_parameter: winapi::shared::basetsd::ULONG_PTR) {}
+
+/// Receives a status notification on the watch thread, storing the new state or the error in the context of the notification
+unsafe extern "system" fn status_notify_callback(parameter: winapi::shared::minwindef::LPVOID) {
+    let notify = &*(parameter as *const winapi::um::winsvc::SERVICE_NOTIFYW);
+    let result = &mut *(notify.pContext as *mut Option<Result<DWORD, DWORD>>);
+    *result = Some(if notify.dwNotificationStatus == 0 {
+        Ok(notify.ServiceStatus.dwCurrentState)
+    } else {
+        Err(notify.dwNotificationStatus)
+    });
+}
+
+/// Wait for the status notifications of the service until the watch is cancelled, calling f with every new status.
+/// Windows notifies right away when the service is already in one of the requested states, so the current state is left out of the next request
+fn watch_service_status<F: FnMut(crate::ServiceStatus)>(
+    service: ServiceHandle,
+    _manager: ServiceController,
+    cancelled: Arc<std::sync::atomic::AtomicBool>,
+    mut f: F,
+) {
+    let mut result: Option<Result<DWORD, DWORD>>;
+    let mut notify: winapi::um::winsvc::SERVICE_NOTIFYW = unsafe { std::mem::zeroed() };
+    let mut states = NOTIFY_STATES;
+    while !cancelled.load(std::sync::atomic::Ordering::SeqCst) {
+        result = None;
+        notify.dwVersion = winapi::um::winsvc::SERVICE_NOTIFY_STATUS_CHANGE;
+        notify.pfnNotifyCallback = Some(status_notify_callback);
+        notify.pContext = &mut result as *mut _ as winapi::shared::minwindef::LPVOID;
+        let e = unsafe {
+            winapi::um::winsvc::NotifyServiceStatusChangeW(
+                service.get_handle(),
+                states,
+                &mut notify,
+            )
+        };
+        if e != 0 {
+            result = Some(Err(e));
+        }
+        while result.is_none() && !cancelled.load(std::sync::atomic::Ordering::SeqCst) {
+            unsafe {
+                winapi::um::synchapi::SleepEx(
+                    winapi::um::winbase::INFINITE,
+                    winapi::shared::minwindef::TRUE,
+                )
+            };
+        }
+        match result {
+            Some(Ok(state)) => {
+                states = NOTIFY_STATES & !notify_flag(state);
+                f(status_from_state(state));
+            }
+            Some(Err(e)) => {
+                if e != winapi::shared::winerror::ERROR_SERVICE_MARKED_FOR_DELETE {
+                    log::error!(
+                        "Unable to watch the service status: {}",
+                        ServiceError::from(e)
+                    );
+                }
+                break;
+            }
+            None => break,
+        }
+    }
+    // closing the handle discards a notification that is still pending, this must happen before notify and result are gone
+    drop(service);
+}
+
+/// Represents a service on the system
+#[derive(Clone)]
+pub struct Service {
+    /// The name of the service, as known by the operating system
+    name: String,
+    /// How long stop waits for the service to stop, 30 seconds by default. None waits forever
+    stop_timeout: Option<std::time::Duration>,
+    /// The event log source of the service, None uses the service name followed by ` Log`
+    log_source: Option<String>,
+}
+
+impl Service {
+    /// Construct a new self, this panics when the name is not a valid service name, see [Service::try_new]
+    pub fn new(name: String) -> Self {
+        Self::try_new(&name).unwrap()
+    }
+
+    /// Construct a new self, checking that the name is accepted by the service control manager
+    pub fn try_new(name: &str) -> Result<Self, crate::InvalidServiceName> {
+        validate_name(name)?;
+        Ok(Self {
+            name: name.to_string(),
+            stop_timeout: Some(DEFAULT_TIMEOUT),
+            log_source: None,
+        })
+    }
+
+    /// Returns true when the current process was started by the service control manager, instead of from a terminal.
+    /// This checks if the parent process is services.exe
+    pub fn is_launched_as_service() -> bool {
+        use winapi::um::tlhelp32::{Process32FirstW, Process32NextW, PROCESSENTRY32W};
+        let snapshot = unsafe {
+            winapi::um::tlhelp32::CreateToolhelp32Snapshot(
+                winapi::um::tlhelp32::TH32CS_SNAPPROCESS,
+                0,
+            )
+        };
+        if snapshot == winapi::um::handleapi::INVALID_HANDLE_VALUE {
+            return false;
+        }
+        let mut processes = Vec::new();
+        let mut entry: PROCESSENTRY32W = unsafe { std::mem::zeroed() };
+        entry.dwSize = std::mem::size_of::<PROCESSENTRY32W>() as DWORD;
+        let mut more = unsafe { Process32FirstW(snapshot, &mut entry) } != 0;
+        while more {
+            let exe = unsafe { from_utf16_ptr(entry.szExeFile.as_ptr()) };
+            processes.push((entry.th32ProcessID, entry.th32ParentProcessID, exe));
+            more = unsafe { Process32NextW(snapshot, &mut entry) } != 0;
+        }
+        unsafe { winapi::um::handleapi::CloseHandle(snapshot) };
+        let me = std::process::id();
+        let Some(parent) = processes.iter().find(|p| p.0 == me).map(|p| p.1) else {
+            return false;
+        };
+        processes
+            .iter()
+            .any(|p| p.0 == parent && p.2.eq_ignore_ascii_case("services.exe"))
+    }
+
+    /// Set how long stop waits for the service to stop before returning an error, None waits forever
+    pub fn set_stop_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.stop_timeout = timeout;
+    }
+
+    /// The name of the service
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Renaming is not possible with the service control manager, this always returns [CreateServiceError::RenameNotSupported].
+    /// Only the display name of a service can be changed, with update
+    pub fn rename(&mut self, _name: &str) -> Result<(), CreateServiceError> {
+        Err(CreateServiceError::RenameNotSupported)
+    }
+
+    /// Set the event log source used by new_log, create, and recent_logs, so that several services can log under one source.
+    /// A custom source is not deregistered by delete, since other services may still use it.
+    pub fn set_log_source(&mut self, source: Option<String>) {
+        self.log_source = source;
+    }
+
+    /// The event log source of the service
+    pub fn log_source(&self) -> String {
+        match &self.log_source {
+            Some(source) => source.clone(),
+            None => format!("{} Log", self.name),
+        }
+    }
+
+    /// Initialize a new log instance. No tracing subscriber is installed, so with the tracing feature, tracing events only reach the event log through the log fallback of tracing, which is used while no subscriber is set
+    pub fn new_log(&self, level: super::LogLevel) -> Result<(), super::LogInitError> {
+        let Some(level) = level.level() else {
+            log::set_max_level(log::LevelFilter::Off);
+            return Ok(());
+        };
+        eventlog::init(&self.log_source(), level).map_err(|e| match e {
+            eventlog::InitError::Set(_) => super::LogInitError::AlreadyInitialized,
+            e => super::LogInitError::BackendFailed(e.to_string()),
+        })
+    }
+
+    /// List the names of the installed services, optionally only those starting with prefix
+    pub fn list(prefix: Option<&str>) -> Result<Vec<String>, ServiceError> {
+        let service_manager =
+            ServiceController::open(winapi::um::winsvc::SC_MANAGER_ENUMERATE_SERVICE)?;
+        let mut names = Vec::new();
+        let mut resume: DWORD = 0;
+        // u64 keeps the buffer aligned for the pointers in ENUM_SERVICE_STATUS_PROCESSW
+        let mut buffer: Vec<u64> = Vec::new();
+        loop {
+            let mut needed: DWORD = 0;
+            let mut count: DWORD = 0;
+            let r = unsafe {
+                winapi::um::winsvc::EnumServicesStatusExW(
+                    service_manager.get_handle(),
+                    winapi::um::winsvc::SC_ENUM_PROCESS_INFO,
+                    winapi::um::winnt::SERVICE_WIN32,
+                    winapi::um::winsvc::SERVICE_STATE_ALL,
+                    buffer.as_mut_ptr() as *mut u8,
+                    (buffer.len() * std::mem::size_of::<u64>()) as DWORD,
+                    &mut needed,
+                    &mut count,
+                    &mut resume,
+                    std::ptr::null(),
+                )
+            };
+            let more = if r == 0 {
+                let e = ServiceError::last();
+                if e.code() != winapi::shared::winerror::ERROR_MORE_DATA {
+                    return Err(e);
+                }
+                true
+            } else {
+                false
+            };
+            let entries =
+                buffer.as_ptr() as *const winapi::um::winsvc::ENUM_SERVICE_STATUS_PROCESSW;
+            for i in 0..count as usize {
+                let name = unsafe {
+                    widestring::WideCString::from_ptr_str((*entries.add(i)).lpServiceName)
+                }
+                .to_string_lossy();
+                if prefix.map(|p| name.starts_with(p)).unwrap_or(true) {
+                    names.push(name);
+                }
+            }
+            if !more {
+                break;
+            }
+            let size = (needed as usize + 7) / 8;
+            if size > buffer.len() {
+                buffer.resize(size, 0);
+            }
+        }
+        Ok(names)
+    }
+
+    /// Does the service already exist? Failing to ask the service manager is logged as a warning and treated as not existing, see [Service::try_exists]
+    pub fn exists(&self) -> bool {
+        self.try_exists().unwrap_or_else(|e| {
+            log::warn!("Unable to check if service {} exists: {}", self.name, e);
+            false
+        })
+    }
+
+    /// Does the service already exist? An error is returned when the service manager cannot be opened or asked about the service
+    pub fn try_exists(&self) -> Result<bool, ServiceError> {
+        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)?;
+        let service =
+            service_manager.open_service(&self.name, winapi::um::winsvc::SERVICE_QUERY_STATUS);
+        match service.map_err(ServiceError::from) {
+            Ok(_) => Ok(true),
+            Err(ServiceError::ServiceDoesNotExist) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Open the service once for start, stop, and status, instead of opening it again for every call
+    pub fn connect(&self) -> Result<OpenService, ServiceError> {
+        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)?;
+        let service = service_manager.open_service(
+            &self.name,
+            winapi::um::winsvc::SERVICE_START
+                | winapi::um::winsvc::SERVICE_STOP
+                | winapi::um::winsvc::SERVICE_QUERY_STATUS,
+        )?;
+        Ok(OpenService {
+            service,
+            _manager: service_manager,
+            stop_timeout: self.stop_timeout,
+        })
+    }
+
+    /// Stop the service, stopping a service that is not running does nothing and succeeds
+    pub fn stop(&mut self) -> Result<(), ServiceError> {
+        let poll = PollConfig {
+            timeout: self.stop_timeout,
+            ..PollConfig::default()
+        };
+        self.stop_with_poll(&poll).map(|_| ())
+    }
+
+    /// Stop the service, waiting for it to stop as set by poll. Returns how long the stop took.
+    /// Stopping a service that is not running does nothing and succeeds
+    pub fn stop_with_poll(
+        &mut self,
+        poll: &PollConfig,
+    ) -> Result<std::time::Duration, ServiceError> {
+        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)?;
+        let service = service_manager.open_service(
+            &self.name,
+            winapi::um::winsvc::SERVICE_STOP | winapi::um::winsvc::SERVICE_QUERY_STATUS,
+        )?;
+        stop_handle(&service, poll)
+    }
+
+    #[cfg(feature = "async")]
+    /// Stop the service, stopping a service that is not running does nothing and succeeds
+    pub async fn stop_async(&mut self) -> Result<(), ServiceError> {
+        run_blocking(self, |s| s.stop()).await
+    }
+
+    /// Start the service, starting a service that is already running does nothing and succeeds
+    pub fn start(&mut self) -> Result<(), StartServiceError> {
+        self.start_with_args(&[])
+    }
+
+    /// Start the service with start parameters for this start only, the service function receives them after the name of the service.
+    /// These are separate from the arguments of the config, which are on the command line of the binary. The parameters are ignored when the service is already running
+    pub fn start_with_args(&mut self, args: &[String]) -> Result<(), StartServiceError> {
+        self.start_with_poll(args, &PollConfig::default())
+            .map(|_| ())
+    }
+
+    /// Start the service with start parameters, waiting for it to start as set by poll. Returns how long the start took.
+    /// Starting a service that is already running does nothing and succeeds
+    pub fn start_with_poll(
+        &mut self,
+        args: &[String],
+        poll: &PollConfig,
+    ) -> Result<std::time::Duration, StartServiceError> {
+        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)
+            .map_err(|e| StartServiceError::WindowsError(e.into()))?;
+        let service = service_manager
+            .open_service(
+                &self.name,
+                winapi::um::winsvc::SERVICE_START | winapi::um::winsvc::SERVICE_QUERY_STATUS,
+            )
+            .map_err(|e| StartServiceError::WindowsError(e.into()))?;
+        start_handle(&service, args, poll)
+    }
+
+    #[cfg(feature = "async")]
+    /// Start the service, starting a service that is already running does nothing and succeeds
+    pub async fn start_async(&mut self) -> Result<(), StartServiceError> {
+        run_blocking(self, |s| s.start()).await
+    }
+
+    #[cfg(feature = "async")]
+    /// Start the service with start parameters, see [Service::start_with_args]
+    pub async fn start_with_args_async(
+        &mut self,
+        args: &[String],
+    ) -> Result<(), StartServiceError> {
+        let args = args.to_vec();
+        run_blocking(self, move |s| s.start_with_args(&args)).await
+    }
+
+    /// Send a custom control code between 128 and 255 to the running service, 128 is delivered as [crate::ServiceEvent::Reload]
+    pub fn send_control(&self, code: u32) -> Result<(), ServiceError> {
+        if !(128..=255).contains(&code) {
+            return Err(ServiceError::Other(
+                winapi::shared::winerror::ERROR_INVALID_PARAMETER,
+            ));
+        }
+        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)?;
+        let service = service_manager
+            .open_service(&self.name, winapi::um::winsvc::SERVICE_USER_DEFINED_CONTROL)?;
+        let mut service_status: winapi::um::winsvc::SERVICE_STATUS = unsafe { std::mem::zeroed() };
+        if unsafe {
+            winapi::um::winsvc::ControlService(service.get_handle(), code, &mut service_status)
+        } == 0
+        {
+            return Err(ServiceError::last());
+        }
+        Ok(())
+    }
+
+    /// Query the current status of the service
+    pub fn status(&self) -> Result<crate::ServiceStatus, ServiceError> {
+        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)?;
+        let service = match service_manager
+            .open_service(&self.name, winapi::um::winsvc::SERVICE_QUERY_STATUS)
+        {
+            Ok(s) => s,
+            Err(winapi::shared::winerror::ERROR_SERVICE_DOES_NOT_EXIST) => {
+                return Ok(crate::ServiceStatus::NotInstalled)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        query_status(&service)
+    }
+
+    /// Call f with the current status of the service, and again every time windows reports that the status changed, without polling.
+    /// The callback runs on a thread of the watch, until the returned watch is cancelled or dropped
+    pub fn watch_status<F>(&self, f: F) -> Result<StatusWatch, ServiceError>
+    where
+        F: FnMut(crate::ServiceStatus) + Send + 'static,
+    {
+        let manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)?;
+        let service = manager.open_service(&self.name, winapi::um::winsvc::SERVICE_QUERY_STATUS)?;
+        let cancelled = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let watch_cancelled = cancelled.clone();
+        let thread =
+            std::thread::spawn(move || watch_service_status(service, manager, watch_cancelled, f));
+        Ok(StatusWatch {
+            cancelled,
+            thread: Some(thread),
+        })
+    }
+
+    /// Wait until the service reaches the status, using [Service::watch_status] instead of polling.
+    /// Returns false when the timeout elapses first, or the watch ends because the service was deleted
+    pub fn wait_for_status(
+        &self,
+        status: crate::ServiceStatus,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<bool, ServiceError> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let _watch = self.watch_status(move |s| {
+            let _ = tx.send(s);
+        })?;
+        let deadline = timeout.map(|t| std::time::Instant::now() + t);
+        loop {
+            let next = match deadline {
+                Some(d) => rx
+                    .recv_timeout(d.saturating_duration_since(std::time::Instant::now()))
+                    .ok(),
+                None => rx.recv().ok(),
+            };
+            match next {
+                Some(s) if s == status => return Ok(true),
+                Some(_) => {}
+                None => return Ok(false),
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    /// Query the current status of the service
+    pub async fn status_async(&self) -> Result<crate::ServiceStatus, ServiceError> {
+        run_blocking(self, |s| s.status()).await
+    }
+
+    /// Returns true when the service is running, any error is treated as not running
+    pub fn is_running(&self) -> bool {
+        matches!(self.status(), Ok(crate::ServiceStatus::Running))
+    }
+
+    /// Get the process id of the service, None when the service is not running
+    pub fn pid(&self) -> Result<Option<u32>, ServiceError> {
+        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)?;
+        let service =
+            service_manager.open_service(&self.name, winapi::um::winsvc::SERVICE_QUERY_STATUS)?;
+        let mut status: winapi::um::winsvc::SERVICE_STATUS_PROCESS = unsafe { std::mem::zeroed() };
+        let mut needed: DWORD = 0;
+        let r = unsafe {
+            winapi::um::winsvc::QueryServiceStatusEx(
+                service.get_handle(),
+                winapi::um::winsvc::SC_STATUS_PROCESS_INFO,
+                &mut status as *mut _ as *mut u8,
+                std::mem::size_of::<winapi::um::winsvc::SERVICE_STATUS_PROCESS>() as DWORD,
+                &mut needed,
+            )
+        };
+        if r == 0 {
+            return Err(ServiceError::last());
+        }
+        if status.dwProcessId == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(status.dwProcessId))
+        }
+    }
+
+    #[cfg(feature = "async")]
+    /// Get the process id of the service, None when the service is not running
+    pub async fn pid_async(&self) -> Result<Option<u32>, ServiceError> {
+        run_blocking(self, |s| s.pid()).await
+    }
+
+    /// Get the most recent messages that the service logged to the event log with new_log, oldest first
+    pub fn recent_logs(&self, lines: usize) -> Result<Vec<String>, ServiceError> {
+        let source = self.log_source();
+        let log = unsafe { OpenEventLogW(std::ptr::null(), get_utf16("Application").as_ptr()) };
+        if log.is_null() {
+            return Err(ServiceError::last());
+        }
+        let mut messages = Vec::new();
+        // u64 keeps the buffer aligned for the EVENTLOGRECORD headers
+        let mut buffer: Vec<u64> = vec![0; 8192];
+        let result = loop {
+            if messages.len() >= lines {
+                break Ok(());
+            }
+            let mut read: DWORD = 0;
+            let mut needed: DWORD = 0;
+            let r = unsafe {
+                ReadEventLogW(
+                    log,
+                    winapi::um::winnt::EVENTLOG_SEQUENTIAL_READ
+                        | winapi::um::winnt::EVENTLOG_BACKWARDS_READ,
+                    0,
+                    buffer.as_mut_ptr() as winapi::shared::minwindef::LPVOID,
+                    (buffer.len() * std::mem::size_of::<u64>()) as DWORD,
+                    &mut read,
+                    &mut needed,
+                )
+            };
+            if r == 0 {
+                let e = ServiceError::last();
+                match e.code() {
+                    winapi::shared::winerror::ERROR_HANDLE_EOF => break Ok(()),
+                    winapi::shared::winerror::ERROR_INSUFFICIENT_BUFFER => {
+                        buffer.resize((needed as usize + 7) / 8, 0);
+                        continue;
+                    }
+                    _ => break Err(e),
+                }
+            }
+            let base = buffer.as_ptr() as *const u8;
+            let mut offset = 0;
+            while offset < read as usize && messages.len() < lines {
+                let record =
+                    unsafe { &*(base.add(offset) as *const winapi::um::winnt::EVENTLOGRECORD) };
+                let strings = |start: usize, count: usize| -> Vec<String> {
+                    let mut p = unsafe { base.add(offset + start) as *const u16 };
+                    let mut v = Vec::new();
+                    for _ in 0..count {
+                        let s = unsafe { widestring::WideCStr::from_ptr_str(p) };
+                        p = unsafe { p.add(s.len() + 1) };
+                        v.push(s.to_string_lossy());
+                    }
+                    v
+                };
+                let name = strings(std::mem::size_of::<winapi::um::winnt::EVENTLOGRECORD>(), 1);
+                if name[0] == source {
+                    let parts = strings(record.StringOffset as usize, record.NumStrings as usize);
+                    messages.push(parts.join(" "));
+                }
+                offset += record.Length as usize;
+            }
+        };
+        unsafe { CloseEventLog(log) };
+        result?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    /// Change the start type of the service, leaving the rest of the configuration alone
+    fn set_start_type(&mut self, start_type: DWORD) -> Result<(), ServiceError> {
+        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)?;
+        let service =
+            service_manager.open_service(&self.name, winapi::um::winsvc::SERVICE_CHANGE_CONFIG)?;
+        if unsafe {
+            winapi::um::winsvc::ChangeServiceConfigW(
+                service.get_handle(),
+                winapi::um::winsvc::SERVICE_NO_CHANGE,
+                start_type,
+                winapi::um::winsvc::SERVICE_NO_CHANGE,
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        } == 0
+        {
+            return Err(ServiceError::last());
+        }
+        Ok(())
+    }
+
+    /// Enable the service so that it starts when the system boots
+    pub fn enable(&mut self) -> Result<(), ServiceError> {
+        self.set_start_type(winapi::um::winnt::SERVICE_AUTO_START)
+    }
+
+    /// Disable automatic start of the service, it can still be started manually
+    pub fn disable(&mut self) -> Result<(), ServiceError> {
+        self.set_start_type(winapi::um::winnt::SERVICE_DEMAND_START)
+    }
+
+    /// Restart the service, waiting up to the stop timeout for it to stop and then up to the timeout of [PollConfig::default] for it to be running again
+    pub fn restart(&mut self) -> Result<(), RestartServiceError> {
+        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)
+            .map_err(|e| RestartServiceError::WindowsError(e.into()))?;
+        let service = service_manager
+            .open_service(
+                &self.name,
+                winapi::um::winsvc::SERVICE_START
+                    | winapi::um::winsvc::SERVICE_STOP
+                    | winapi::um::winsvc::SERVICE_QUERY_STATUS,
+            )
+            .map_err(|e| RestartServiceError::WindowsError(e.into()))?;
+        let stop_poll = PollConfig {
+            timeout: self.stop_timeout,
+            ..PollConfig::default()
+        };
+        match stop_handle(&service, &stop_poll) {
+            Ok(_) => {}
+            Err(ServiceError::StopTimeout) => return Err(RestartServiceError::StopTimeout),
+            Err(e) => return Err(RestartServiceError::WindowsError(e)),
+        }
+        match start_handle(&service, &[], &PollConfig::default()) {
+            Ok(_) => Ok(()),
+            Err(StartServiceError::WindowsError(e)) => Err(RestartServiceError::WindowsError(e)),
+            Err(StartServiceError::FailedToStart(state)) => {
+                Err(RestartServiceError::FailedToStart(state))
+            }
+            Err(StartServiceError::StartTimeout) => Err(RestartServiceError::StartTimeout),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    /// Restart the service
+    pub async fn restart_async(&mut self) -> Result<(), RestartServiceError> {
+        run_blocking(self, |s| s.restart()).await
+    }
+
+    /// Delete the service. A service that is still running is only marked for deletion until it stops,
+    /// this is reported as [ServiceError::MarkedForDelete]. Use [Service::stop_and_delete] to stop it first
+    pub fn delete(&mut self) -> Result<(), ServiceError> {
+        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)?;
+        if self.log_source.is_none() {
+            let _e = eventlog::deregister(&self.log_source());
+        }
+        let service = service_manager.open_service(
+            &self.name,
+            winapi::um::winnt::DELETE | winapi::um::winsvc::SERVICE_QUERY_STATUS,
+        )?;
+        if unsafe { winapi::um::winsvc::DeleteService(service.get_handle()) } == 0 {
+            return Err(ServiceError::last());
+        }
+        if !matches!(query_status(&service)?, crate::ServiceStatus::Stopped) {
+            return Err(ServiceError::MarkedForDelete);
+        }
+        Ok(())
+    }
+
+    /// Stop the service when it is running, waiting up to the stop timeout, then delete it.
+    /// Deleting a running service only marks it for deletion, and creating it again fails with [ServiceError::MarkedForDelete] until it stops
+    pub fn stop_and_delete(&mut self) -> Result<(), ServiceError> {
+        self.stop()?;
+        self.delete()
+    }
+
+    #[cfg(feature = "async")]
+    /// Delete the service, see [Service::delete]
+    pub async fn delete_async(&mut self) -> Result<(), ServiceError> {
+        run_blocking(self, |s| s.delete()).await
+    }
+
+    /// Write a value into the registry key of the service
+    fn set_registry_value(
+        &self,
+        value: &str,
+        kind: DWORD,
+        data: &[u16],
+    ) -> Result<(), ServiceError> {
+        let subkey = format!("SYSTEM\\CurrentControlSet\\Services\\{}", self.name);
+        set_local_machine_value(&subkey, value, kind, data)
+    }
+
+    /// Register the event log source of the service with the service binary as its message file.
+    /// The binary contains the messages of the eventlog crate, which Event Viewer uses to show the entries
+    fn register_message_file(&self, binary: &std::path::Path) -> Result<(), ServiceError> {
+        let subkey = format!(
+            "SYSTEM\\CurrentControlSet\\Services\\EventLog\\Application\\{}",
+            self.log_source()
+        );
+        let data = get_utf16(&binary.display().to_string());
+        let kind = winapi::um::winnt::REG_SZ;
+        set_local_machine_value(&subkey, "EventMessageFile", kind, &data)
+    }
+
+    /// Write the environment block for the service into the registry, as a multi-string of KEY=VALUE entries.
+    /// The service control manager provides these variables to the service process when it starts.
+    fn set_environment(&self, environment: &[(String, String)]) -> Result<(), ServiceError> {
+        let mut block: Vec<u16> = Vec::new();
+        for (key, value) in environment {
+            block.extend(get_utf16(&format!("{}={}", key, value)));
         }
+        block.push(0);
+        self.set_registry_value("Environment", winapi::um::winnt::REG_MULTI_SZ, &block)
+    }
 
-        if service_status.dwCurrentState != SERVICE_RUNNING {
-            println!("Failed to start service {}", service_status.dwCurrentState);
-            Err(StartServiceError::FailedToStart(
-                service_status.dwCurrentState,
-            ))
+    /// The config path of the config, or the default when it does not set one
+    fn resolved_config_path(&self, config: &ServiceConfig) -> PathBuf {
+        if config.config_path.as_os_str().is_empty() {
+            self.default_config_path()
         } else {
-            Ok(())
+            config.config_path.clone()
         }
     }
 
-    /// Delete the service
-    pub fn delete(&mut self) -> Result<(), DWORD> {
-        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_ALL_ACCESS)?; //TODO REMOVE RIGHTS NOT REQUIRED
-        let _e = eventlog::deregister(&format!("{} Log", self.name));
-        let service =
-            service_manager.open_service(&self.name, winapi::um::winsvc::SERVICE_ALL_ACCESS)?;
-        if unsafe { winapi::um::winsvc::DeleteService(service.get_handle()) } == 0 {
-            return Err(unsafe { winapi::um::errhandlingapi::GetLastError() });
-        }
-        Ok(())
+    /// Create the config path of the config and write it into the registry, where [Service::config_path] reads it
+    fn set_config_path(&self, config: &ServiceConfig) -> Result<(), CreateServiceError> {
+        let config_path = self.resolved_config_path(config);
+        std::fs::create_dir_all(&config_path).map_err(CreateServiceError::FileIoError)?;
+        let data = get_utf16(&config_path.display().to_string());
+        self.set_registry_value("ConfigPath", winapi::um::winnt::REG_SZ, &data)
+            .map_err(CreateServiceError::WindowsError)
     }
 
-    #[cfg(feature = "async")]
-    /// Delete the service
-    pub async fn delete_async(&mut self) -> Result<(), DWORD> {
-        self.delete()
+    /// The config path used when the config does not set one, `<name>` in `%PROGRAMDATA%`
+    pub fn default_config_path(&self) -> PathBuf {
+        std::env::var_os("PROGRAMDATA")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("C:\\ProgramData"))
+            .join(&self.name)
+    }
+
+    /// The config path of the service, as written in the registry by create, or the default when it is not there
+    pub fn config_path(&self) -> PathBuf {
+        match self.query_registry_value("ConfigPath", winapi::um::winreg::RRF_RT_REG_SZ) {
+            Ok(Some(data)) => PathBuf::from(unsafe { from_utf16_ptr(data.as_ptr()) }),
+            _ => self.default_config_path(),
+        }
     }
 
     /// Create the service
-    pub fn create(&mut self, config: ServiceConfig) -> Result<(), CreateServiceError> {
-        eventlog::register(&format!("{} Log", self.name)).unwrap();
-        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_ALL_ACCESS)
-            .map_err(CreateServiceError::WindowsError)?; //TODO REMOVE RIGHTS NOT REQUIRED
-        let exe = config.binary.as_os_str().to_str().unwrap();
-        let args = config.arguments.join(" ");
-        let exe_with_args = if config.arguments.is_empty() {
-            exe.to_string()
+    pub fn create(&mut self, mut config: ServiceConfig) -> Result<(), CreateServiceError> {
+        config.binary = config.absolute_binary(config.allow_missing_binary)?;
+        let exe_with_args = config.command_line()?;
+        if config.event_message_file {
+            self.register_message_file(&config.binary)
+                .map_err(CreateServiceError::WindowsError)?;
+        } else {
+            eventlog::register(&self.log_source()).map_err(CreateServiceError::EventLogError)?;
+        }
+        let service_manager =
+            ServiceController::open(winapi::um::winsvc::SC_MANAGER_CREATE_SERVICE)
+                .map_err(|e| CreateServiceError::WindowsError(e.into()))?;
+        let dependencies = if config.dependencies.is_empty() {
+            None
+        } else {
+            Some(get_multi_utf16(&config.dependencies))
+        };
+        // The buffers are bound here so that they outlive the call to CreateServiceW
+        let name = get_utf16(&self.name);
+        let display = get_utf16(&config.display);
+        let exe_with_args = get_utf16(&exe_with_args);
+        let load_order_group = get_optional_utf16(config.load_order_group.as_deref());
+        let (username, password) = if config.per_user {
+            (None, None)
         } else {
-            format!("{} {}", exe, args)
+            (
+                get_optional_utf16(config.username.as_deref()),
+                get_optional_utf16(config.user_password.as_deref()),
+            )
         };
         let service = unsafe {
             winapi::um::winsvc::CreateServiceW(
                 service_manager.get_handle(),
-                get_utf16(&self.name).as_ptr(),
-                get_utf16(&config.display).as_ptr(),
+                name.as_ptr(),
+                display.as_ptr(),
                 config.desired_access,
-                config.service_type,
-                config.start_type,
+                config.windows_service_type(),
+                config.windows_start_type(),
                 config.error_control,
-                get_utf16(&exe_with_args).as_ptr(),
-                get_optional_utf16(config.load_order_group.as_deref()),
+                exe_with_args.as_ptr(),
+                optional_utf16_ptr(&load_order_group),
                 std::ptr::null_mut(),
-                get_optional_utf16(config.dependencies.as_deref()),
-                get_optional_utf16(config.username.as_deref()),
-                get_optional_utf16(config.user_password.as_deref()),
+                optional_utf16_ptr(&dependencies),
+                optional_utf16_ptr(&username),
+                optional_utf16_ptr(&password),
             )
         };
         if service.is_null() {
-            return Err(CreateServiceError::WindowsError(unsafe {
-                winapi::um::errhandlingapi::GetLastError()
-            }));
+            return Err(CreateServiceError::WindowsError(ServiceError::last()));
         }
         let mut description = get_utf16(&config.description);
 
@@ -390,8 +2420,14 @@ impl Service {
                 p_sd,
             )
         };
+        let optional = set_optional_config(service, &config);
         unsafe { winapi::um::winsvc::CloseServiceHandle(service) };
-        Ok(())
+        optional.map_err(CreateServiceError::WindowsError)?;
+        if !config.environment.is_empty() {
+            self.set_environment(&config.environment)
+                .map_err(CreateServiceError::WindowsError)?;
+        }
+        self.set_config_path(&config)
     }
 
     #[cfg(feature = "async")]
@@ -400,6 +2436,338 @@ impl Service {
         self.create(config)
     }
 
+    /// Change the configuration of an existing service in place, keeping the event log registration.
+    /// A service without a username is changed back to running as LocalSystem. Changes to the binary take effect the next time the service starts.
+    /// The settings are changed one after the other, so when this fails partway the service is left with only some of the new settings
+    pub fn update(&mut self, mut config: ServiceConfig) -> Result<(), CreateServiceError> {
+        config.binary = config.absolute_binary(config.allow_missing_binary)?;
+        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)
+            .map_err(|e| CreateServiceError::WindowsError(e.into()))?;
+        let service = service_manager
+            .open_service(&self.name, config.change_access())
+            .map_err(|e| CreateServiceError::WindowsError(e.into()))?;
+        let exe_with_args = get_utf16(&config.command_line()?);
+        let load_order_group = get_utf16(config.load_order_group.as_deref().unwrap_or(""));
+        let dependencies = get_multi_utf16(&config.dependencies);
+        // A per user service keeps its account, which windows manages, so null leaves it unchanged
+        let (username, password) = if config.per_user {
+            (None, None)
+        } else {
+            let username = config.username.as_deref().unwrap_or("LocalSystem");
+            let password = config.user_password.as_deref().unwrap_or("");
+            (Some(get_utf16(username)), Some(get_utf16(password)))
+        };
+        let display = get_utf16(&config.display);
+        let r = unsafe {
+            winapi::um::winsvc::ChangeServiceConfigW(
+                service.get_handle(),
+                config.windows_service_type(),
+                config.windows_start_type(),
+                config.error_control,
+                exe_with_args.as_ptr(),
+                load_order_group.as_ptr(),
+                std::ptr::null_mut(),
+                dependencies.as_ptr(),
+                optional_utf16_ptr(&username),
+                optional_utf16_ptr(&password),
+                display.as_ptr(),
+            )
+        };
+        if r == 0 {
+            return Err(CreateServiceError::WindowsError(ServiceError::last()));
+        }
+        let mut description = get_utf16(&config.description);
+        let mut sd = winapi::um::winsvc::SERVICE_DESCRIPTIONW {
+            lpDescription: description.as_mut_ptr(),
+        };
+        let p_sd = &mut sd as *mut _ as *mut winapi::ctypes::c_void;
+        let r = unsafe {
+            winapi::um::winsvc::ChangeServiceConfig2W(
+                service.get_handle(),
+                winapi::um::winsvc::SERVICE_CONFIG_DESCRIPTION,
+                p_sd,
+            )
+        };
+        if r == 0 {
+            return Err(CreateServiceError::WindowsError(ServiceError::last()));
+        }
+        set_optional_config(service.get_handle(), &config)
+            .map_err(CreateServiceError::WindowsError)?;
+        self.set_environment(&config.environment)
+            .map_err(CreateServiceError::WindowsError)?;
+        self.set_config_path(&config)
+    }
+
+    /// Change only the description of the service, without touching the rest of its configuration
+    pub fn set_description(&mut self, description: &str) -> Result<(), ServiceError> {
+        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)?;
+        let service =
+            service_manager.open_service(&self.name, winapi::um::winsvc::SERVICE_CHANGE_CONFIG)?;
+        let mut description = get_utf16(description);
+        let mut sd = winapi::um::winsvc::SERVICE_DESCRIPTIONW {
+            lpDescription: description.as_mut_ptr(),
+        };
+        let p_sd = &mut sd as *mut _ as *mut winapi::ctypes::c_void;
+        let r = unsafe {
+            winapi::um::winsvc::ChangeServiceConfig2W(
+                service.get_handle(),
+                winapi::um::winsvc::SERVICE_CONFIG_DESCRIPTION,
+                p_sd,
+            )
+        };
+        if r == 0 {
+            Err(ServiceError::last())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Create the service when it does not exist, otherwise update it to the config
+    pub fn ensure(
+        &mut self,
+        config: ServiceConfig,
+    ) -> Result<crate::EnsureOutcome, CreateServiceError> {
+        if self.exists() {
+            self.update(config)?;
+            Ok(crate::EnsureOutcome::Updated)
+        } else {
+            self.create(config)?;
+            Ok(crate::EnsureOutcome::Created)
+        }
+    }
+
+    /// Read back the configuration of the installed service. The password of the user cannot be read and is always none.
+    pub fn query_config(&self) -> Result<ServiceConfig, ServiceError> {
+        let service_manager = ServiceController::open(winapi::um::winsvc::SC_MANAGER_CONNECT)?;
+        let service =
+            service_manager.open_service(&self.name, winapi::um::winsvc::SERVICE_QUERY_CONFIG)?;
+        let mut needed: DWORD = 0;
+        unsafe {
+            winapi::um::winsvc::QueryServiceConfigW(
+                service.get_handle(),
+                std::ptr::null_mut(),
+                0,
+                &mut needed,
+            )
+        };
+        let mut buf: Vec<u64> = vec![0; (needed as usize + 7) / 8 + 1];
+        let qsc = buf.as_mut_ptr() as *mut winapi::um::winsvc::QUERY_SERVICE_CONFIGW;
+        let r = unsafe {
+            winapi::um::winsvc::QueryServiceConfigW(
+                service.get_handle(),
+                qsc,
+                (buf.len() * 8) as DWORD,
+                &mut needed,
+            )
+        };
+        if r == 0 {
+            return Err(ServiceError::last());
+        }
+        let qsc = unsafe { &*qsc };
+
+        let mut argc: winapi::ctypes::c_int = 0;
+        let argv =
+            unsafe { winapi::um::shellapi::CommandLineToArgvW(qsc.lpBinaryPathName, &mut argc) };
+        if argv.is_null() {
+            return Err(ServiceError::last());
+        }
+        let mut arguments = unsafe { convert_args(argc as DWORD, argv) };
+        unsafe { winapi::um::winbase::LocalFree(argv as winapi::shared::minwindef::HLOCAL) };
+        let binary = if arguments.is_empty() {
+            PathBuf::new()
+        } else {
+            PathBuf::from(arguments.remove(0))
+        };
+
+        let username = unsafe { from_utf16_ptr(qsc.lpServiceStartName) };
+        let username = if username.is_empty() || username.eq_ignore_ascii_case("LocalSystem") {
+            None
+        } else {
+            Some(username)
+        };
+        let load_order_group = unsafe { from_utf16_ptr(qsc.lpLoadOrderGroup) };
+
+        let mut config = ServiceConfig::new(arguments, String::new(), binary, username);
+        config.display = unsafe { from_utf16_ptr(qsc.lpDisplayName) };
+        config.service_type = qsc.dwServiceType;
+        config.per_user = qsc.dwServiceType & winapi::um::winnt::SERVICE_USER_SERVICE != 0;
+        config.start_type = qsc.dwStartType;
+        config.error_control = qsc.dwErrorControl;
+        config.tag_id = qsc.dwTagId;
+        if !load_order_group.is_empty() {
+            config.load_order_group = Some(load_order_group);
+        }
+        config.dependencies = unsafe { from_multi_utf16(qsc.lpDependencies) };
+
+        let buf = query_config2(
+            service.get_handle(),
+            winapi::um::winsvc::SERVICE_CONFIG_DESCRIPTION,
+        )?;
+        let sd = unsafe { &*(buf.as_ptr() as *const winapi::um::winsvc::SERVICE_DESCRIPTIONW) };
+        config.description = unsafe { from_utf16_ptr(sd.lpDescription) };
+
+        let buf = query_config2(
+            service.get_handle(),
+            winapi::um::winsvc::SERVICE_CONFIG_FAILURE_ACTIONS,
+        )?;
+        let sfa =
+            unsafe { &*(buf.as_ptr() as *const winapi::um::winsvc::SERVICE_FAILURE_ACTIONSW) };
+        if sfa.cActions > 0 && !sfa.lpsaActions.is_null() {
+            let actions =
+                unsafe { std::slice::from_raw_parts(sfa.lpsaActions, sfa.cActions as usize) };
+            let command = unsafe { from_utf16_ptr(sfa.lpCommand) };
+            let reboot_message = unsafe { from_utf16_ptr(sfa.lpRebootMsg) };
+            config.failure_actions = Some(FailureActions {
+                reset_period: std::time::Duration::from_secs(sfa.dwResetPeriod as u64),
+                actions: actions
+                    .iter()
+                    .map(|a| {
+                        let action = match a.Type {
+                            winapi::um::winsvc::SC_ACTION_RESTART => FailureAction::RestartService,
+                            winapi::um::winsvc::SC_ACTION_RUN_COMMAND => FailureAction::RunCommand,
+                            winapi::um::winsvc::SC_ACTION_REBOOT => FailureAction::Reboot,
+                            _ => FailureAction::None,
+                        };
+                        (action, std::time::Duration::from_millis(a.Delay as u64))
+                    })
+                    .collect(),
+                command: Some(command).filter(|c| !c.is_empty()),
+                reboot_message: Some(reboot_message).filter(|r| !r.is_empty()),
+            });
+        }
+
+        if config.start_type == winapi::um::winnt::SERVICE_AUTO_START {
+            let buf = query_config2(
+                service.get_handle(),
+                winapi::um::winsvc::SERVICE_CONFIG_DELAYED_AUTO_START_INFO,
+            )?;
+            config.delayed_auto_start = buf[0] as DWORD != 0;
+        }
+
+        let buf = query_config2(
+            service.get_handle(),
+            winapi::um::winsvc::SERVICE_CONFIG_SERVICE_SID_INFO,
+        )?;
+        config.sid_type = match buf[0] as DWORD {
+            winapi::um::winsvc::SERVICE_SID_TYPE_UNRESTRICTED => SidType::Unrestricted,
+            winapi::um::winsvc::SERVICE_SID_TYPE_RESTRICTED => SidType::Restricted,
+            _ => SidType::None,
+        };
+
+        let buf = query_config2(
+            service.get_handle(),
+            winapi::um::winsvc::SERVICE_CONFIG_PRESHUTDOWN_INFO,
+        )?;
+        config.preshutdown_timeout = Some(std::time::Duration::from_millis(buf[0] as DWORD as u64));
+        config.triggers = query_triggers(service.get_handle())?;
+
+        config.environment = self.query_environment()?;
+        let flags = winapi::um::winreg::RRF_RT_REG_SZ;
+        if let Some(data) = self.query_registry_value("ConfigPath", flags)? {
+            config.config_path = PathBuf::from(unsafe { from_utf16_ptr(data.as_ptr()) });
+        }
+        Ok(config)
+    }
+
+    /// Read a value from the registry key of the service, none when the value is missing
+    fn query_registry_value(
+        &self,
+        value: &str,
+        flags: DWORD,
+    ) -> Result<Option<Vec<u16>>, ServiceError> {
+        let subkey = get_utf16(&format!(
+            "SYSTEM\\CurrentControlSet\\Services\\{}",
+            self.name
+        ));
+        let value_name = get_utf16(value);
+        let mut size: DWORD = 0;
+        let e = unsafe {
+            winapi::um::winreg::RegGetValueW(
+                winapi::um::winreg::HKEY_LOCAL_MACHINE,
+                subkey.as_ptr(),
+                value_name.as_ptr(),
+                flags,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                &mut size,
+            )
+        };
+        if e == winapi::shared::winerror::ERROR_FILE_NOT_FOUND as i32 {
+            return Ok(None);
+        }
+        if e != 0 {
+            return Err(ServiceError::from(e as DWORD));
+        }
+        let mut block: Vec<u16> = vec![0; size as usize / 2 + 2];
+        let mut size = (block.len() * 2) as DWORD;
+        let e = unsafe {
+            winapi::um::winreg::RegGetValueW(
+                winapi::um::winreg::HKEY_LOCAL_MACHINE,
+                subkey.as_ptr(),
+                value_name.as_ptr(),
+                flags,
+                std::ptr::null_mut(),
+                block.as_mut_ptr() as *mut winapi::ctypes::c_void,
+                &mut size,
+            )
+        };
+        if e != 0 {
+            return Err(ServiceError::from(e as DWORD));
+        }
+        Ok(Some(block))
+    }
+
+    /// Read the environment block of the service from the registry, a missing block is an empty environment
+    fn query_environment(&self) -> Result<Vec<(String, String)>, ServiceError> {
+        let flags = winapi::um::winreg::RRF_RT_REG_MULTI_SZ;
+        let Some(block) = self.query_registry_value("Environment", flags)? else {
+            return Ok(Vec::new());
+        };
+        Ok(unsafe { from_multi_utf16(block.as_ptr()) }
+            .into_iter()
+            .map(|v| match v.split_once('=') {
+                Some((k, v)) => (k.to_string(), v.to_string()),
+                None => (v, String::new()),
+            })
+            .collect())
+    }
+
+    /// Run the service function in the foreground without the service control manager, ctrl-c stops the service
+    pub fn run_standalone<T: Send + 'static>(&self, service_main: ServiceFn<T>) -> u32 {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx2: std::sync::mpsc::Sender<crate::ServiceEvent<T>> = tx.clone();
+        *CONSOLE_STOP.lock().unwrap() = Some(Box::new(move || {
+            let _ = tx.send(crate::ServiceEvent::Stop);
+        }));
+        unsafe {
+            winapi::um::consoleapi::SetConsoleCtrlHandler(
+                Some(console_ctrl_handler),
+                winapi::shared::minwindef::TRUE,
+            )
+        };
+        let args = std::env::args().collect();
+        let code = service_main(Some(rx), Some(tx2), args, true).report();
+        unsafe {
+            winapi::um::consoleapi::SetConsoleCtrlHandler(
+                Some(console_ctrl_handler),
+                winapi::shared::minwindef::FALSE,
+            )
+        };
+        *CONSOLE_STOP.lock().unwrap() = None;
+        code
+    }
+
+    /// Tell windows that a service started with [crate::RunConfig::wait_for_ready] is still starting.
+    /// The checkpoint should increase with every call, and the next call should come within wait_hint.
+    pub fn report_progress(checkpoint: u32, wait_hint: std::time::Duration) {
+        report_start_progress(checkpoint, wait_hint);
+    }
+
+    /// Tell windows that a service started with [crate::RunConfig::wait_for_ready] has finished starting
+    pub fn report_running() {
+        report_current_state(winapi::um::winsvc::SERVICE_RUNNING);
+    }
+
     /// Run the required dispatch code for windows
     pub fn dispatch(&self, service_main: DispatchFn) -> Result<(), DWORD> {
         // The service is setup with SERVICE_WIN32_OWN_PROCESS, so this argument is ignored, but cannot be null
@@ -430,73 +2798,90 @@ impl Service {
 #[macro_export]
 macro_rules! ServiceMacro {
     ($entry:ident, $function:ident, $t:ident) => {
+        service::ServiceMacro!($entry, $function, $t, service::RunConfig::default());
+    };
+    ($entry:ident, $function:ident, $t:ident, $config:expr) => {
         extern "system" fn $entry(
             argc: service::winapi::shared::minwindef::DWORD,
             argv: *mut service::winapi::um::winnt::LPWSTR,
         ) {
             let args = unsafe { service::convert_args(argc, argv) };
-            service::run_service::<$t>($function, args);
+            service::run_service::<$t>($function, args, &$config);
         }
     };
 }
 
 #[cfg(feature = "async")]
-/// The macro generates the service function required for windows
+/// The macro generates the service function required for windows.
+/// The optional runtime argument is evaluated in the service thread and runs the service function, it can be a `tokio::runtime::Runtime` or the `tokio::runtime::Handle` of a runtime that is shared with the rest of the program. It is windows only, elsewhere the service runs on the runtime of the caller.
+/// A multi threaded runtime is built for the service when it is not given
 #[macro_export]
 macro_rules! ServiceAsyncMacro {
     ($entry:ident, $function:ident, $t:ident) => {
+        service::ServiceAsyncMacro!($entry, $function, $t, service::RunConfig::default());
+    };
+    ($entry:ident, $function:ident, $t:ident, $config:expr) => {
+        service::ServiceAsyncMacro!(
+            $entry,
+            $function,
+            $t,
+            $config,
+            tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+        );
+    };
+    ($entry:ident, $function:ident, $t:ident, $config:expr, $runtime:expr) => {
         extern "system" fn $entry(
             argc: service::winapi::shared::minwindef::DWORD,
             argv: *mut service::winapi::um::winnt::LPWSTR,
         ) {
             let args = unsafe { service::convert_args(argc, argv) };
-            let name = args.get(0).unwrap();
-            let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+            let config: service::RunConfig<$t> = $config;
+            let controls_accepted = config.controls_accepted();
+            service::set_custom_control(&config);
+            service::reset_stop_requested();
+            let name = config.handler_name(&args);
+            let (tx, rx) = tokio::sync::mpsc::channel(10);
             let tx2: tokio::sync::mpsc::Sender<service::ServiceEvent<$t>> = tx.clone();
-            let mut tx = Box::new(tx);
+            // The handler borrows the sender through this pointer until the service is stopped, then it is freed below
+            let context = Box::into_raw(Box::new(tx));
             let handle = unsafe {
                 service::winapi::um::winsvc::RegisterServiceCtrlHandlerExW(
                     service::get_utf16(&name).as_ptr(),
                     Some(service::service_handler_async::<$t>),
-                    Box::into_raw(tx) as service::winapi::shared::minwindef::LPVOID,
+                    context as service::winapi::shared::minwindef::LPVOID,
                 )
             };
             let mut sh = service::SERVICE_HANDLE.lock().unwrap();
-            *sh = service::ServiceStatusHandle::new(handle);
+            *sh = service::ServiceStatusHandle::new(handle, controls_accepted);
             drop(sh);
             unsafe {
                 service::set_service_status(
                     handle,
                     service::winapi::um::winsvc::SERVICE_START_PENDING,
+                    controls_accepted,
                     0,
                 )
             };
-            unsafe {
-                service::set_service_status(handle, service::winapi::um::winsvc::SERVICE_RUNNING, 0)
-            };
-            let runtime = tokio::runtime::Builder::new_multi_thread()
-                .enable_all()
-                .build()
-                .unwrap();
-            runtime.block_on(async move {
-                {
-                    let main = tokio::task::spawn(smain());
-                    loop {
-                        tokio::select! {
-                            Some(m) = rx.recv() => {
-                                if let service::ServiceEvent::Stop = m {
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                    main.abort();
-                    0
-                }
-            });
-            unsafe {
-                service::set_service_status(handle, service::winapi::um::winsvc::SERVICE_STOPPED, 0)
-            };
+            if !config.wait_for_ready {
+                unsafe {
+                    service::set_service_status(
+                        handle,
+                        service::winapi::um::winsvc::SERVICE_RUNNING,
+                        controls_accepted,
+                        0,
+                    )
+                };
+            }
+            let runtime = $runtime;
+            let service_main = $function(rx, tx2, args, false);
+            let drain = service::run_with_drain_timeout(service_main, config.stop_drain_timeout);
+            let code = runtime.block_on(drain);
+            unsafe { service::set_service_stopped(handle, code) };
+            // windows no longer calls the handler once the service is stopped
+            drop(unsafe { Box::from_raw(context) });
         }
     };
 }
@@ -509,31 +2894,78 @@ macro_rules! DispatchAsync {
     }};
 }
 
+/// Report that the running service is still starting, using the global service handle
+fn report_start_progress(checkpoint: DWORD, wait_hint: std::time::Duration) {
+    use std::ops::DerefMut;
+    let mut sh = SERVICE_HANDLE.lock().unwrap();
+    let ServiceStatusHandle(h, controls) = sh.deref_mut();
+    let state = winapi::um::winsvc::SERVICE_START_PENDING;
+    unsafe { report_service_status(*h, state, *controls, checkpoint, wait_hint) };
+}
+
+/// Report a new state for the running service, using the global service handle
+fn report_current_state(state: DWORD) {
+    use std::ops::DerefMut;
+    let mut sh = SERVICE_HANDLE.lock().unwrap();
+    let ServiceStatusHandle(h, controls) = sh.deref_mut();
+    unsafe { set_service_status(*h, state, *controls, 0) };
+}
+
+#[cfg(feature = "async")]
 #[tokio::main]
+/// Send the stop event to the async service from outside of a runtime
 async fn do_the_thing<T>(tx: &mut tokio::sync::mpsc::Sender<crate::ServiceEvent<T>>) {
-    tx.send(crate::ServiceEvent::Stop).await;
+    let _ = tx.send(crate::ServiceEvent::Stop).await;
 }
 
-fn do_service_handle<T>(
-    mut tx: Box<tokio::sync::mpsc::Sender<crate::ServiceEvent<T>>>, 
+#[cfg(feature = "async")]
+/// Handle a single command from windows for an async service
+fn do_service_handle<T: 'static>(
+    mut tx: tokio::sync::mpsc::Sender<crate::ServiceEvent<T>>,
     control: DWORD,
     event_type: winapi::shared::minwindef::DWORD,
     session_id: Option<u32>,
 ) -> DWORD {
     match control {
-        winapi::um::winsvc::SERVICE_CONTROL_STOP | winapi::um::winsvc::SERVICE_CONTROL_SHUTDOWN => {
+        winapi::um::winsvc::SERVICE_CONTROL_STOP
+        | winapi::um::winsvc::SERVICE_CONTROL_SHUTDOWN
+        | winapi::um::winsvc::SERVICE_CONTROL_PRESHUTDOWN => {
             use std::ops::DerefMut;
             let mut sh = SERVICE_HANDLE.lock().unwrap();
-            let ServiceStatusHandle(h) = sh.deref_mut();
-            unsafe { set_service_status(*h, winapi::um::winsvc::SERVICE_STOP_PENDING, 10) };
+            let ServiceStatusHandle(h, controls) = sh.deref_mut();
+            unsafe {
+                set_service_status(*h, winapi::um::winsvc::SERVICE_STOP_PENDING, *controls, 10)
+            };
             drop(sh);
+            note_stop_requested();
             do_the_thing(&mut tx);
             //let _ = (*tx).blocking_send(crate::ServiceEvent::Stop);
             0
         }
+        winapi::um::winsvc::SERVICE_CONTROL_PAUSE => {
+            report_current_state(winapi::um::winsvc::SERVICE_PAUSED);
+            let _ = tx.blocking_send(crate::ServiceEvent::Pause);
+            0
+        }
+        winapi::um::winsvc::SERVICE_CONTROL_CONTINUE => {
+            report_current_state(winapi::um::winsvc::SERVICE_RUNNING);
+            let _ = tx.blocking_send(crate::ServiceEvent::Continue);
+            0
+        }
+        SERVICE_CONTROL_RELOAD => {
+            let _ = tx.blocking_send(crate::ServiceEvent::Reload);
+            0
+        }
+        129..=255 => match custom_event::<T>(control) {
+            Some(m) => {
+                let _ = tx.blocking_send(crate::ServiceEvent::Custom(m));
+                0
+            }
+            None => winapi::shared::winerror::ERROR_CALL_NOT_IMPLEMENTED,
+        },
         winapi::um::winsvc::SERVICE_CONTROL_SESSIONCHANGE => {
             let event = event_type;
-            let session = Session(session_id.unwrap());
+            let session = Session::query(session_id.unwrap());
 
             match event as usize {
                 winapi::um::winuser::WTS_CONSOLE_CONNECT => {
@@ -572,6 +3004,18 @@ fn do_service_handle<T>(
                 _ => 0,
             }
         }
+        winapi::um::winsvc::SERVICE_CONTROL_POWEREVENT => {
+            match event_type as usize {
+                winapi::um::winuser::PBT_APMSUSPEND => {
+                    let _ = tx.blocking_send(crate::ServiceEvent::PowerSuspend);
+                }
+                winapi::um::winuser::PBT_APMRESUMEAUTOMATIC => {
+                    let _ = tx.blocking_send(crate::ServiceEvent::PowerResume);
+                }
+                _ => {}
+            }
+            0
+        }
         _ => winapi::shared::winerror::ERROR_CALL_NOT_IMPLEMENTED,
     }
 }
@@ -580,31 +3024,32 @@ fn do_service_handle<T>(
 /// The service handler for async code. This receives commands from windows to control the service being run.
 /// # Safety
 ///
-/// context must be a valid `std::sync::mpsc::Sender<crate::ServiceEvent<T>>`, defined in `RegisterServiceCtrlHandlerExW``
+/// context must be a valid `tokio::sync::mpsc::Sender<crate::ServiceEvent<T>>`, defined in `RegisterServiceCtrlHandlerExW`.
+/// The handler only borrows the sender, it is owned and freed by the code that registered the handler.
 pub unsafe extern "system" fn service_handler_async<T: Send + 'static>(
     control: winapi::shared::minwindef::DWORD,
     event_type: winapi::shared::minwindef::DWORD,
     event_data: winapi::shared::minwindef::LPVOID,
     context: winapi::shared::minwindef::LPVOID,
 ) -> winapi::shared::minwindef::DWORD {
-    let tx = context as *mut tokio::sync::mpsc::Sender<crate::ServiceEvent<T>>;
-    let tx = Box::from_raw(tx);
-    let session_notification =
-        event_data as *const winapi::um::winuser::WTSSESSION_NOTIFICATION;
-    let session_id = 
-    if let winapi::um::winsvc::SERVICE_CONTROL_SESSIONCHANGE = event_type {
-        Some(unsafe { (*session_notification).dwSessionId })
-    } else { None };
-    std::thread::spawn(move || {
-        do_service_handle(tx, control, event_type, session_id)
-    }).join().unwrap()
+    let tx = (*(context as *const tokio::sync::mpsc::Sender<crate::ServiceEvent<T>>)).clone();
+    let session_notification = event_data as *const winapi::um::winuser::WTSSESSION_NOTIFICATION;
+    let session_id = if let winapi::um::winsvc::SERVICE_CONTROL_SESSIONCHANGE = control {
+        Some((*session_notification).dwSessionId)
+    } else {
+        None
+    };
+    std::thread::spawn(move || do_service_handle(tx, control, event_type, session_id))
+        .join()
+        .unwrap()
 }
 
 /// Run the service handler for a windows service, handling commands from windows to control the service
 /// # Safety
 ///
-/// context must be a valid `std::sync::mpsc::Sender<crate::ServiceEvent<T>>`, defined in `RegisterServiceCtrlHandlerExW``
-pub unsafe extern "system" fn service_handler<T>(
+/// context must be a valid `std::sync::mpsc::Sender<crate::ServiceEvent<T>>`, defined in `RegisterServiceCtrlHandlerExW`.
+/// The handler only borrows the sender, it is owned and freed by the code that registered the handler.
+pub unsafe extern "system" fn service_handler<T: 'static>(
     control: winapi::shared::minwindef::DWORD,
     event_type: winapi::shared::minwindef::DWORD,
     event_data: winapi::shared::minwindef::LPVOID,
@@ -612,29 +3057,45 @@ pub unsafe extern "system" fn service_handler<T>(
 ) -> winapi::shared::minwindef::DWORD {
     let tx = context as *mut std::sync::mpsc::Sender<crate::ServiceEvent<T>>;
     match control {
-        winapi::um::winsvc::SERVICE_CONTROL_STOP | winapi::um::winsvc::SERVICE_CONTROL_SHUTDOWN => {
+        winapi::um::winsvc::SERVICE_CONTROL_STOP
+        | winapi::um::winsvc::SERVICE_CONTROL_SHUTDOWN
+        | winapi::um::winsvc::SERVICE_CONTROL_PRESHUTDOWN => {
             use std::ops::DerefMut;
             let mut sh = SERVICE_HANDLE.lock().unwrap();
-            let ServiceStatusHandle(h) = sh.deref_mut();
-            set_service_status(*h, winapi::um::winsvc::SERVICE_STOP_PENDING, 10);
+            let ServiceStatusHandle(h, controls) = sh.deref_mut();
+            set_service_status(*h, winapi::um::winsvc::SERVICE_STOP_PENDING, *controls, 10);
             drop(sh);
+            note_stop_requested();
             let _ = (*tx).send(crate::ServiceEvent::Stop);
             0
         }
         winapi::um::winsvc::SERVICE_CONTROL_PAUSE => {
+            report_current_state(winapi::um::winsvc::SERVICE_PAUSED);
             let _ = (*tx).send(crate::ServiceEvent::Pause);
             0
         }
         winapi::um::winsvc::SERVICE_CONTROL_CONTINUE => {
+            report_current_state(winapi::um::winsvc::SERVICE_RUNNING);
             let _ = (*tx).send(crate::ServiceEvent::Continue);
             0
         }
+        SERVICE_CONTROL_RELOAD => {
+            let _ = (*tx).send(crate::ServiceEvent::Reload);
+            0
+        }
+        129..=255 => match custom_event::<T>(control) {
+            Some(m) => {
+                let _ = (*tx).send(crate::ServiceEvent::Custom(m));
+                0
+            }
+            None => winapi::shared::winerror::ERROR_CALL_NOT_IMPLEMENTED,
+        },
         winapi::um::winsvc::SERVICE_CONTROL_SESSIONCHANGE => {
             let event = event_type;
             let session_notification =
                 event_data as *const winapi::um::winuser::WTSSESSION_NOTIFICATION;
             let session_id = (*session_notification).dwSessionId;
-            let session = Session(session_id);
+            let session = Session::query(session_id);
 
             match event as usize {
                 winapi::um::winuser::WTS_CONSOLE_CONNECT => {
@@ -672,6 +3133,18 @@ pub unsafe extern "system" fn service_handler<T>(
                 _ => 0,
             }
         }
+        winapi::um::winsvc::SERVICE_CONTROL_POWEREVENT => {
+            match event_type as usize {
+                winapi::um::winuser::PBT_APMSUSPEND => {
+                    let _ = (*tx).send(crate::ServiceEvent::PowerSuspend);
+                }
+                winapi::um::winuser::PBT_APMRESUMEAUTOMATIC => {
+                    let _ = (*tx).send(crate::ServiceEvent::PowerResume);
+                }
+                _ => {}
+            }
+            0
+        }
         _ => winapi::shared::winerror::ERROR_CALL_NOT_IMPLEMENTED,
     }
 }
@@ -683,43 +3156,271 @@ pub unsafe extern "system" fn service_handler<T>(
 pub unsafe fn set_service_status(
     status_handle: winapi::um::winsvc::SERVICE_STATUS_HANDLE,
     current_state: winapi::shared::minwindef::DWORD,
+    controls_accepted: winapi::shared::minwindef::DWORD,
     wait_hint: winapi::shared::minwindef::DWORD,
+) {
+    let wait_hint = std::time::Duration::from_millis(wait_hint as u64);
+    let controls = controls_accepted;
+    report_service_status(status_handle, current_state, controls, 0, wait_hint);
+}
+
+/// Report the status of the service to windows, with the checkpoint of a pending state and how long until the next report.
+/// The wait hint is rounded down to whole milliseconds, and clamped to the longest wait hint windows accepts
+/// # Safety
+///
+/// status_handle must be valid
+pub unsafe fn report_service_status(
+    status_handle: winapi::um::winsvc::SERVICE_STATUS_HANDLE,
+    current_state: DWORD,
+    controls_accepted: DWORD,
+    checkpoint: u32,
+    wait_hint: std::time::Duration,
 ) {
     let mut service_status = winapi::um::winsvc::SERVICE_STATUS {
         dwServiceType: winapi::um::winnt::SERVICE_WIN32_OWN_PROCESS,
         dwCurrentState: current_state,
-        dwControlsAccepted: winapi::um::winsvc::SERVICE_ACCEPT_STOP
-            | winapi::um::winsvc::SERVICE_ACCEPT_SHUTDOWN,
+        dwControlsAccepted: controls_accepted,
         dwWin32ExitCode: 0,
         dwServiceSpecificExitCode: 0,
-        dwCheckPoint: 0,
-        dwWaitHint: wait_hint,
+        dwCheckPoint: checkpoint,
+        dwWaitHint: wait_hint.as_millis().min(DWORD::MAX as u128) as DWORD,
     };
     winapi::um::winsvc::SetServiceStatus(status_handle, &mut service_status);
     //TODO determine if the function errored, then call GetLastError
 }
 
+/// Report that the service has stopped, a non zero exit code is reported as a service specific error
+/// # Safety
+///
+/// status_handle must be valid
+pub unsafe fn set_service_stopped(
+    status_handle: winapi::um::winsvc::SERVICE_STATUS_HANDLE,
+    exit_code: DWORD,
+) {
+    let mut service_status = winapi::um::winsvc::SERVICE_STATUS {
+        dwServiceType: winapi::um::winnt::SERVICE_WIN32_OWN_PROCESS,
+        dwCurrentState: winapi::um::winsvc::SERVICE_STOPPED,
+        dwControlsAccepted: 0,
+        dwWin32ExitCode: if exit_code == 0 {
+            0
+        } else {
+            winapi::shared::winerror::ERROR_SERVICE_SPECIFIC_ERROR
+        },
+        dwServiceSpecificExitCode: exit_code,
+        dwCheckPoint: 0,
+        dwWaitHint: 0,
+    };
+    winapi::um::winsvc::SetServiceStatus(status_handle, &mut service_status);
+}
+
 /// Runs the main service function
-pub fn run_service<T: std::marker::Send + 'static>(service_main: ServiceFn<T>, args: Vec<String>) {
-    let name = args.first().unwrap();
+pub fn run_service<T: std::marker::Send + 'static>(
+    service_main: ServiceFn<T>,
+    args: Vec<String>,
+    config: &crate::RunConfig<T>,
+) {
+    let controls_accepted = config.controls_accepted();
+    set_custom_control(config);
+    reset_stop_requested();
+    let name = config.handler_name(&args);
     let (tx, rx) = std::sync::mpsc::channel();
     let tx2: std::sync::mpsc::Sender<crate::ServiceEvent<T>> = tx.clone();
-    let tx = Box::new(tx);
+    // The handler borrows the sender through this pointer until the service is stopped, then it is freed below
+    let context = Box::into_raw(Box::new(tx));
     let handle = unsafe {
         winapi::um::winsvc::RegisterServiceCtrlHandlerExW(
-            get_utf16(name).as_ptr(),
+            get_utf16(&name).as_ptr(),
             Some(service_handler::<T>),
-            Box::into_raw(tx) as winapi::shared::minwindef::LPVOID,
+            context as winapi::shared::minwindef::LPVOID,
         )
     };
     let mut sh = SERVICE_HANDLE.lock().unwrap();
-    *sh = ServiceStatusHandle(handle);
+    *sh = ServiceStatusHandle(handle, controls_accepted);
     drop(sh);
-    unsafe { set_service_status(handle, winapi::um::winsvc::SERVICE_START_PENDING, 0) };
-    unsafe { set_service_status(handle, winapi::um::winsvc::SERVICE_RUNNING, 0) };
-    let service_thread = std::thread::spawn(move || {
-        service_main(Some(rx), Some(tx2));
-    });
-    let _e = service_thread.join();
-    unsafe { set_service_status(handle, winapi::um::winsvc::SERVICE_STOPPED, 0) };
+    unsafe {
+        set_service_status(
+            handle,
+            winapi::um::winsvc::SERVICE_START_PENDING,
+            controls_accepted,
+            0,
+        )
+    };
+    if !config.wait_for_ready {
+        unsafe {
+            set_service_status(
+                handle,
+                winapi::um::winsvc::SERVICE_RUNNING,
+                controls_accepted,
+                0,
+            )
+        };
+    }
+    let service_args = args.clone();
+    let service_thread =
+        std::thread::spawn(move || service_main(Some(rx), Some(tx2), service_args, false));
+    if config.stop_drain_timeout.is_some() {
+        while !service_thread.is_finished() && !drain_expired(config.stop_drain_timeout) {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+    }
+    let code = if service_thread.is_finished() || config.stop_drain_timeout.is_none() {
+        // a panic in the service function is reported as a failure
+        service_thread
+            .join()
+            .map(crate::ServiceExit::report)
+            .unwrap_or(1)
+    } else {
+        log::error!("The service did not stop within the stop drain timeout");
+        DRAIN_TIMEOUT_EXIT_CODE
+    };
+    unsafe { set_service_stopped(handle, code) };
+    // windows no longer calls the handler once the service is stopped
+    drop(unsafe { Box::from_raw(context) });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Arguments that need quoting or escaping on a windows command line
+    const ARGS: [&str; 11] = [
+        "plain",
+        "with space",
+        "quote\"inside",
+        "back\\slash",
+        "trailing\\",
+        "trailing space\\",
+        "\\\\\"",
+        "100%",
+        "$HOME",
+        "",
+        "tab\there",
+    ];
+
+    /// Split a command line the way CommandLineToArgvW does.
+    /// The program name ends at the next quote when it starts with one, otherwise at whitespace, without any escapes.
+    /// In the other arguments 2n backslashes before a quote become n backslashes and the quote toggles quoting,
+    /// 2n+1 backslashes before a quote become n backslashes and a literal quote, and other backslashes are literal
+    fn command_line_to_argv(line: &str) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut chars = line.chars().peekable();
+        let mut program = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            for c in chars.by_ref() {
+                if c == '"' {
+                    break;
+                }
+                program.push(c);
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !matches!(c, ' ' | '\t')) {
+                program.push(c);
+            }
+        }
+        args.push(program);
+        loop {
+            while chars.next_if(|c| matches!(c, ' ' | '\t')).is_some() {}
+            if chars.peek().is_none() {
+                break;
+            }
+            let mut arg = String::new();
+            let mut quoted = false;
+            loop {
+                let mut backslashes = 0;
+                while chars.next_if_eq(&'\\').is_some() {
+                    backslashes += 1;
+                }
+                match chars.peek() {
+                    Some('"') => {
+                        arg.extend(std::iter::repeat('\\').take(backslashes / 2));
+                        chars.next();
+                        if backslashes % 2 == 1 {
+                            arg.push('"');
+                        } else if quoted && chars.peek() == Some(&'"') {
+                            chars.next();
+                            arg.push('"');
+                        } else {
+                            quoted = !quoted;
+                        }
+                    }
+                    Some(' ' | '\t') if !quoted => {
+                        arg.extend(std::iter::repeat('\\').take(backslashes));
+                        break;
+                    }
+                    Some(_) => {
+                        arg.extend(std::iter::repeat('\\').take(backslashes));
+                        arg.push(chars.next().unwrap());
+                    }
+                    None => {
+                        arg.extend(std::iter::repeat('\\').take(backslashes));
+                        break;
+                    }
+                }
+            }
+            args.push(arg);
+        }
+        args
+    }
+
+    #[test]
+    fn exec_args_round_trip() {
+        let exe = "C:\\Program Files\\app\\bin.exe";
+        let mut line = escape_exec_arg(exe);
+        for arg in ARGS {
+            line.push(' ');
+            line.push_str(&escape_exec_arg(arg));
+        }
+        let mut expected = vec![exe.to_string()];
+        expected.extend(ARGS.iter().map(|a| a.to_string()));
+        assert_eq!(command_line_to_argv(&line), expected);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn config_from_json_builds_command_line() {
+        let json = r#"{
+            "binary": "C:\\Program Files\\app\\bin.exe",
+            "arguments": ["--port", "80 80", "C:\\data dir\\"],
+            "description": "Example service"
+        }"#;
+        let config: ServiceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.description(), "Example service");
+        let line = config.command_line().unwrap();
+        assert_eq!(
+            command_line_to_argv(&line),
+            [
+                "C:\\Program Files\\app\\bin.exe",
+                "--port",
+                "80 80",
+                "C:\\data dir\\"
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_program_name_is_not_quoted() {
+        let exe = "C:\\app\\bin.exe";
+        assert_eq!(escape_exec_arg(exe), exe);
+        assert_eq!(command_line_to_argv(exe), vec![exe.to_string()]);
+    }
+
+    #[test]
+    fn request_timeout_is_not_a_stop_timeout() {
+        let code = winapi::shared::winerror::ERROR_SERVICE_REQUEST_TIMEOUT;
+        assert_eq!(ServiceError::from(code), ServiceError::Other(code));
+    }
+
+    #[test]
+    fn verbatim_prefix_is_stripped() {
+        let strip = |p: &str| strip_verbatim(PathBuf::from(p));
+        assert_eq!(
+            strip(r"\\?\C:\app\bin.exe"),
+            PathBuf::from(r"C:\app\bin.exe")
+        );
+        assert_eq!(
+            strip(r"\\?\UNC\server\share\bin.exe"),
+            PathBuf::from(r"\\server\share\bin.exe")
+        );
+        assert_eq!(strip(r"C:\app\bin.exe"), PathBuf::from(r"C:\app\bin.exe"));
+    }
 }